@@ -1,8 +1,3 @@
-use ringbuf::{SharedRb, storage::Heap, traits::Producer, wrap::caching::Caching};
-use std::sync::Arc;
-
-const NR52: usize = 0x26;
-const NR10: usize = 0x10;
 const NR11: usize = 0x11;
 const NR12: usize = 0x12;
 const NR13: usize = 0x13;
@@ -12,34 +7,51 @@ const NR22: usize = 0x17;
 const NR23: usize = 0x18;
 const NR24: usize = 0x19;
 const NR30: usize = 0x1A;
+const NR42: usize = 0x21;
+const NR43: usize = 0x22;
+const NR44: usize = 0x23;
+const NR50: usize = 0x24;
+const NR51: usize = 0x25;
 const T_CYCLES_PER_SAMPLE: f32 = 4194304.0 / 44100.0;
+// Samples past this are dropped until the frontend drains the buffer (interleaved L/R)
+const MAX_SAMPLES: usize = 8192;
 
 use crate::{
-    apu::{channel::AudioChannel, pulse::PulseChannel, wave::WaveChannel},
+    apu::{channel::AudioChannel, noise::NoiseChannel, pulse::PulseChannel, wave::WaveChannel},
     cpu::alu,
+    error::GBError,
     mem::map::Memory,
+    state::{SaveState, StateReader, StateWriter},
 };
 
 pub struct APU {
     last_cycle: u64,
     accumulator: f32,
     frame_sequencer: u8,
-    pub buffer: Caching<Arc<SharedRb<Heap<f32>>>, true, false>,
+    pub samples: Vec<f32>,
     pub pulse_1: PulseChannel,
     pub pulse_2: PulseChannel,
     pub wave: WaveChannel,
+    pub noise: NoiseChannel,
+}
+
+impl Default for APU {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl APU {
-    pub fn new(buffer: Caching<Arc<SharedRb<Heap<f32>>>, true, false>) -> Self {
+    pub fn new() -> Self {
         APU {
             accumulator: 0.0,
             last_cycle: 0,
             frame_sequencer: 0,
-            buffer,
+            samples: Vec::with_capacity(MAX_SAMPLES),
             pulse_1: PulseChannel::default(),
             pulse_2: PulseChannel::default(),
             wave: WaveChannel::default(),
+            noise: NoiseChannel::default(),
         }
     }
     pub fn init(&mut self, mem: &Memory) {
@@ -50,9 +62,11 @@ impl APU {
         // Volume
         self.pulse_1.volume = alu::read_bits(mem.io[NR12], 4, 4);
         self.pulse_2.volume = alu::read_bits(mem.io[NR22], 4, 4);
+        self.noise.volume = alu::read_bits(mem.io[NR42], 4, 4);
         // Period
         self.pulse_1.read_period(mem.io[NR13], mem.io[NR14]);
         self.pulse_2.read_period(mem.io[NR23], mem.io[NR24]);
+        self.noise.read_polynomial(mem.io[NR43]);
     }
     pub fn tick(&mut self, mem: &Memory) {
         self.last_cycle += 4;
@@ -64,17 +78,19 @@ impl APU {
                 self.pulse_1.length_tick();
                 self.pulse_2.length_tick();
                 self.wave.length_tick();
+                self.noise.length_tick();
             }
             if self.frame_sequencer == 7 {
                 self.pulse_1.vol_sweep();
                 self.pulse_2.vol_sweep();
+                self.noise.vol_sweep();
             } else if self.frame_sequencer == 2 || self.frame_sequencer == 6 {
                 // TODO: This function is still broken, fix it
                 self.pulse_1.period_sweep();
             }
         }
         // Channel 1
-        self.pulse_1.duty_cycle = match alu::read_bits(mem.io[0x11], 6, 2) {
+        self.pulse_1.duty_cycle = match alu::read_bits(mem.io[NR11], 6, 2) {
             0b00 => 0,
             0b01 => 1,
             0b10 => 2,
@@ -83,7 +99,7 @@ impl APU {
         };
         self.pulse_1.length_enable = alu::read_bits(mem.io[NR14], 6, 1) == 1;
         // Channel 2
-        self.pulse_2.duty_cycle = match alu::read_bits(mem.io[0x16], 6, 2) {
+        self.pulse_2.duty_cycle = match alu::read_bits(mem.io[NR21], 6, 2) {
             0b00 => 0,
             0b01 => 1,
             0b10 => 2,
@@ -91,16 +107,74 @@ impl APU {
             _ => unreachable!(),
         };
         self.pulse_2.length_enable = alu::read_bits(mem.io[NR24], 6, 1) == 1;
+        // Channel 4
+        self.noise.length_enable = alu::read_bits(mem.io[NR44], 6, 1) == 1;
         let ch1 = self.pulse_1.tick();
         let ch2 = self.pulse_2.tick();
         let mut ch3 = 0.0;
-        if self.wave.dac_enable == true {
+        if self.wave.dac_enable {
             ch3 = self.wave.tick();
             ch3 = (ch3 + self.wave.tick()) / 2.0;
         }
+        let ch4 = self.noise.tick();
+        let (left, right) = Self::mix([ch1, ch2, ch3, ch4], mem.io[NR50], mem.io[NR51]);
         while self.accumulator >= T_CYCLES_PER_SAMPLE {
             self.accumulator -= T_CYCLES_PER_SAMPLE;
-            self.buffer.try_push((ch1 + ch2 + ch3) / 3.0);
+            if self.samples.len() + 2 <= MAX_SAMPLES {
+                self.samples.push(left);
+                self.samples.push(right);
+            }
+        }
+    }
+    /// Pans the channels with NR51 and scales each side by its NR50 master volume
+    fn mix(channels: [f32; 4], nr50: u8, nr51: u8) -> (f32, f32) {
+        let mut left = 0.0;
+        let mut right = 0.0;
+        for (i, sample) in channels.iter().enumerate() {
+            if alu::read_bits(nr51, i as u8 + 4, 1) == 1 {
+                left += sample;
+            }
+            if alu::read_bits(nr51, i as u8, 1) == 1 {
+                right += sample;
+            }
+        }
+        let left_volume = (alu::read_bits(nr50, 4, 3) + 1) as f32 / 8.0;
+        let right_volume = (alu::read_bits(nr50, 0, 3) + 1) as f32 / 8.0;
+        (left / 4.0 * left_volume, right / 4.0 * right_volume)
+    }
+}
+
+impl SaveState for APU {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.u64(self.last_cycle);
+        w.f32(self.accumulator);
+        w.u8(self.frame_sequencer);
+        self.pulse_1.save_state(w);
+        self.pulse_2.save_state(w);
+        self.wave.save_state(w);
+        self.noise.save_state(w);
+    }
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), GBError> {
+        self.last_cycle = r.u64()?;
+        self.accumulator = r.f32()?;
+        self.frame_sequencer = r.u8()? & 7;
+        self.pulse_1.load_state(r)?;
+        self.pulse_2.load_state(r)?;
+        self.wave.load_state(r)?;
+        if r.version >= 2 {
+            self.noise.load_state(r)?;
         }
+        self.samples.clear();
+        Ok(())
     }
 }
+
+#[test]
+fn mix_test() {
+    // CH1 hard left, CH2 hard right, left at full volume and right at 1/8
+    let (left, right) = APU::mix([1.0, 0.5, 0.0, 0.0], 0x70, 0b0001_0010);
+    assert_eq!(left, 0.25);
+    assert_eq!(right, 0.5 / 4.0 / 8.0);
+    let (left, right) = APU::mix([1.0, 1.0, 1.0, 1.0], 0x77, 0);
+    assert_eq!((left, right), (0.0, 0.0));
+}
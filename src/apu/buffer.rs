@@ -1,11 +1,6 @@
 use std::sync::Arc;
 
-use ringbuf::{
-    SharedRb,
-    storage::Heap,
-    traits::{Consumer, Observer},
-    wrap::caching::Caching,
-};
+use ringbuf::{SharedRb, storage::Heap, traits::Consumer, wrap::caching::Caching};
 use sdl3::audio::AudioCallback;
 
 pub struct AudioBuffer {
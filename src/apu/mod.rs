@@ -1,5 +1,8 @@
+#[allow(clippy::module_inception)]
 pub mod apu;
+#[cfg(feature = "sdl")]
 pub mod buffer;
 pub mod channel;
+pub mod noise;
 pub mod pulse;
 pub mod wave;
@@ -0,0 +1,168 @@
+use crate::{
+    apu::channel::AudioChannel,
+    cpu::alu,
+    error::GBError,
+    state::{SaveState, StateReader, StateWriter},
+};
+
+// NR43 clock divider in M-cycles (8, 16, 32... T-cycles)
+const DIVIDER_TABLE: [u32; 8] = [2, 4, 8, 12, 16, 20, 24, 28];
+
+#[derive(Default)]
+pub struct NoiseChannel {
+    div: u32,
+    lfsr: u16,
+    output: bool,
+    pub(super) vol_timer: u8,
+    pub length_timer: u8,
+    pub(super) length_enable: bool,
+    pub is_on: bool,
+    pub(super) vol_period: u8,
+    pub(super) vol_inc: bool,
+    pub(super) volume: u8,
+    clock_shift: u8,
+    is_short: bool,
+    divider: u8,
+}
+
+impl AudioChannel for NoiseChannel {
+    fn tick(&mut self) -> f32 {
+        if !self.is_on {
+            return 0.0;
+        }
+        let sample = if self.output {
+            self.volume as f32 / 15.0
+        } else {
+            0.0
+        };
+        if self.div != 0 {
+            self.div -= 1;
+        } else {
+            self.div = self.period();
+            // Clock shifts 14 and 15 stop the LFSR
+            if self.clock_shift < 14 {
+                // The channel outputs the inverse of bit 0
+                self.output = !alu::lfsr_shift(&mut self.lfsr, self.is_short);
+            }
+        }
+        sample
+    }
+    fn reset(&mut self, nrx2: u8, nrx3: u8, _nrx4: u8) {
+        // DAC is off when the upper 5 bits of NR42 are cleared
+        self.is_on = alu::read_bits(nrx2, 3, 5) != 0;
+        if self.length_timer == 0 {
+            self.length_timer = 64;
+        }
+        self.read_polynomial(nrx3);
+        self.vol_period = alu::read_bits(nrx2, 0, 3);
+        self.vol_timer = self.vol_period;
+        self.vol_inc = alu::read_bits(nrx2, 3, 1) == 1;
+        self.volume = alu::read_bits(nrx2, 4, 4);
+        self.lfsr = 0x7FFF;
+        self.output = false;
+        self.div = self.period();
+    }
+}
+
+impl NoiseChannel {
+    /// Loads NR43, can change mid-note without a retrigger
+    pub fn read_polynomial(&mut self, nrx3: u8) {
+        self.clock_shift = alu::read_bits(nrx3, 4, 4);
+        self.is_short = alu::read_bits(nrx3, 3, 1) == 1;
+        self.divider = alu::read_bits(nrx3, 0, 3);
+    }
+    fn period(&self) -> u32 {
+        (DIVIDER_TABLE[self.divider as usize] << self.clock_shift) - 1
+    }
+    pub fn length_tick(&mut self) {
+        if self.length_enable && self.length_timer != 0 {
+            self.length_timer -= 1;
+            if self.length_timer == 0 {
+                self.is_on = false;
+            }
+        }
+    }
+    pub fn vol_sweep(&mut self) {
+        if self.vol_period != 0 {
+            self.vol_timer -= 1;
+            if self.vol_timer == 0 {
+                self.vol_timer = self.vol_period;
+                if self.vol_inc && self.volume != 15 {
+                    self.volume += 1;
+                } else if !self.vol_inc && self.volume != 0 {
+                    self.volume -= 1;
+                }
+            }
+        }
+    }
+}
+
+impl SaveState for NoiseChannel {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.u32(self.div);
+        w.u16(self.lfsr);
+        w.bool(self.output);
+        w.u8(self.vol_timer);
+        w.u8(self.length_timer);
+        w.bool(self.length_enable);
+        w.bool(self.is_on);
+        w.u8(self.vol_period);
+        w.bool(self.vol_inc);
+        w.u8(self.volume);
+        w.u8(self.clock_shift);
+        w.bool(self.is_short);
+        w.u8(self.divider);
+    }
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), GBError> {
+        self.div = r.u32()?;
+        self.lfsr = r.u16()?;
+        self.output = r.bool()?;
+        self.vol_timer = r.u8()?;
+        self.length_timer = r.u8()?;
+        self.length_enable = r.bool()?;
+        self.is_on = r.bool()?;
+        self.vol_period = r.u8()?;
+        self.vol_inc = r.bool()?;
+        self.volume = r.u8()?;
+        self.clock_shift = r.u8()? & 0xF;
+        self.is_short = r.bool()?;
+        self.divider = r.u8()? & 7;
+        Ok(())
+    }
+}
+
+#[test]
+fn noise_test() {
+    let mut noise = NoiseChannel::default();
+    // Full volume, divider 8 T-cycles and no shift: the LFSR clocks every other M-cycle
+    noise.reset(0xF0, 0x00, 0x80);
+    let bits = |noise: &mut NoiseChannel, count: usize| -> Vec<bool> {
+        (0..count)
+            .map(|_| {
+                let sample = noise.tick();
+                noise.tick();
+                sample != 0.0
+            })
+            .collect()
+    };
+    assert_eq!((noise.tick(), noise.tick()), (0.0, 0.0));
+    // Bit 0 of the all ones LFSR stays set for 14 shifts, the inverted output goes high on the 15th
+    let first_high = bits(&mut noise, 16).iter().position(|&bit| bit);
+    assert_eq!(first_high, Some(14));
+    let long = bits(&mut noise, 0x8000 * 2);
+    assert_eq!(long[..0x7FFF], long[0x7FFF..0xFFFE]);
+    assert_ne!(long[..0x3FFF], long[0x3FFF..0x7FFE]);
+    // Width mode repeats every 127 clocks
+    noise.reset(0x80, 0x08, 0x80);
+    noise.tick();
+    noise.tick();
+    let samples: Vec<f32> = (0..16).map(|_| noise.tick()).collect();
+    assert!(samples.contains(&(8.0 / 15.0)));
+    let short = bits(&mut noise, 300);
+    assert_eq!(short[..127], short[127..254]);
+    assert_ne!(short[..63], short[63..126]);
+    // Clock shifts 14 and 15 freeze the LFSR
+    noise.reset(0xF0, 0xE0, 0x80);
+    noise.div = 0;
+    assert!(bits(&mut noise, 100).iter().all(|&bit| !bit));
+}
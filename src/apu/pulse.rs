@@ -1,4 +1,9 @@
-use crate::{apu::channel::AudioChannel, cpu::alu};
+use crate::{
+    apu::channel::AudioChannel,
+    cpu::alu,
+    error::GBError,
+    state::{SaveState, StateReader, StateWriter},
+};
 
 const DUTY_TABLE: [[u8; 8]; 4] = [
     [0, 0, 0, 0, 0, 0, 0, 1],
@@ -113,3 +118,41 @@ impl PulseChannel {
         }
     }
 }
+
+impl SaveState for PulseChannel {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.u32(self.phase);
+        w.u32(self.div);
+        w.u8(self.period_timer);
+        w.u8(self.vol_timer);
+        w.u8(self.length_timer);
+        w.bool(self.length_enable);
+        w.bool(self.is_on);
+        w.u8(self.period_pace);
+        w.u8(self.period_step);
+        w.bool(self.period_inc);
+        w.u8(self.vol_period);
+        w.bool(self.vol_inc);
+        w.u32(self.period);
+        w.u8(self.duty_cycle as u8);
+        w.u8(self.volume);
+    }
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), GBError> {
+        self.phase = r.u32()? & 7;
+        self.div = r.u32()?;
+        self.period_timer = r.u8()?;
+        self.vol_timer = r.u8()?;
+        self.length_timer = r.u8()?;
+        self.length_enable = r.bool()?;
+        self.is_on = r.bool()?;
+        self.period_pace = r.u8()?;
+        self.period_step = r.u8()?;
+        self.period_inc = r.bool()?;
+        self.vol_period = r.u8()?;
+        self.vol_inc = r.bool()?;
+        self.period = r.u32()?;
+        self.duty_cycle = r.u8()? as usize & 3;
+        self.volume = r.u8()?;
+        Ok(())
+    }
+}
@@ -1,4 +1,9 @@
-use crate::{apu::channel::AudioChannel, cpu::alu};
+use crate::{
+    apu::channel::AudioChannel,
+    cpu::alu,
+    error::GBError,
+    state::{SaveState, StateReader, StateWriter},
+};
 
 const VOLUME_TABLE: [f32; 4] = [0.0, 1.0, 0.5, 0.25];
 
@@ -67,3 +72,29 @@ impl WaveChannel {
         }
     }
 }
+
+impl SaveState for WaveChannel {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.bool(self.is_on);
+        w.bool(self.dac_enable);
+        w.u16(self.length_timer);
+        w.bool(self.length_enable);
+        w.u8(self.phase);
+        w.u32(self.div);
+        w.u32(self.period);
+        w.bytes(&self.wave_pattern);
+        w.u8(self.volume);
+    }
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), GBError> {
+        self.is_on = r.bool()?;
+        self.dac_enable = r.bool()?;
+        self.length_timer = r.u16()?;
+        self.length_enable = r.bool()?;
+        self.phase = r.u8()? & 31;
+        self.div = r.u32()?;
+        self.period = r.u32()?;
+        r.fill(&mut self.wave_pattern)?;
+        self.volume = r.u8()? & 3;
+        Ok(())
+    }
+}
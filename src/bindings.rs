@@ -0,0 +1,264 @@
+//! Keyboard and gamepad bindings for the SDL frontend
+//!
+//! Bindings live in `bindings.cfg` in the config dir, a default one is written on first launch.
+//! Every line maps a Game Boy button to a comma separated list of inputs:
+//!
+//! ```text
+//! a = key:Z, pad:a
+//! left = key:Left, pad:dpleft, axis:leftx-
+//! dead_zone = 8000
+//! ```
+//!
+//! `key:` takes SDL key names, `pad:` and `axis:` take SDL gamepad mapping names, axes need a
+//! `+` or `-` for the direction. Malformed entries are logged and skipped, so are keys taken by
+//! the emulator [`HOTKEYS`].
+//!
+//! A button stays pressed as long as any input bound to it is held, letting go of a key doesn't
+//! release a button the gamepad is still holding.
+
+use std::path::PathBuf;
+
+use log::{error, info, warn};
+use sdl3::gamepad::{Axis, Button as PadButton};
+use sdl3::keyboard::Keycode;
+
+use crate::cpu::input::Button;
+
+const DEFAULT_CONFIG: &str = "\
+# Game Boy button = comma separated inputs
+# key:<SDL key name>, pad:<SDL gamepad button>, axis:<SDL gamepad axis><+ or ->
+a = key:Z, pad:a
+b = key:X, pad:b
+select = key:C, pad:back
+start = key:Return, pad:start
+up = key:Up, pad:dpup, axis:lefty-
+down = key:Down, pad:dpdown, axis:lefty+
+left = key:Left, pad:dpleft, axis:leftx-
+right = key:Right, pad:dpright, axis:leftx+
+# Stick deflection (0-32767) below which axes count as released
+dead_zone = 8000
+";
+const DEFAULT_DEAD_ZONE: i16 = 8000;
+
+/// Emulator functions on fixed keys
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hotkey {
+    Quit,
+    Pause,
+    FrameAdvance,
+    FastForward,
+    Rewind,
+    SaveState,
+    LoadState,
+    Record,
+    Screenshot,
+}
+
+/// Matched by the frontend before the bindings, which can't use these keys
+pub const HOTKEYS: [(Keycode, Hotkey); 9] = [
+    (Keycode::Escape, Hotkey::Quit),
+    (Keycode::P, Hotkey::Pause),
+    (Keycode::N, Hotkey::FrameAdvance),
+    (Keycode::Tab, Hotkey::FastForward),
+    (Keycode::Backspace, Hotkey::Rewind),
+    (Keycode::F5, Hotkey::SaveState),
+    (Keycode::F9, Hotkey::LoadState),
+    (Keycode::F10, Hotkey::Record),
+    (Keycode::F12, Hotkey::Screenshot),
+];
+
+pub fn hotkey(keycode: Keycode) -> Option<Hotkey> {
+    HOTKEYS
+        .iter()
+        .find(|&&(key, _)| key == keycode)
+        .map(|&(_, hotkey)| hotkey)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Input {
+    Key(Keycode),
+    Pad(PadButton),
+    /// Axis and whether it's bound to the positive direction
+    Axis(Axis, bool),
+}
+
+#[derive(Clone)]
+pub struct Bindings {
+    binds: Vec<(Input, Button)>,
+    /// Whether the input of each bind is currently held
+    held: Vec<bool>,
+    pub dead_zone: i16,
+}
+
+impl Bindings {
+    /// Loads the bindings from the config dir, writing the defaults there if there's no file yet
+    pub fn load() -> Self {
+        let path = config_path();
+        match std::fs::read_to_string(&path) {
+            Ok(config) => {
+                info!("Loading bindings from {}", path.display());
+                Self::parse(&config)
+            }
+            Err(_) => {
+                if let Err(e) = std::fs::write(&path, DEFAULT_CONFIG) {
+                    error!("Couldn't write default bindings: {e}");
+                }
+                Self::parse(DEFAULT_CONFIG)
+            }
+        }
+    }
+
+    pub fn parse(config: &str) -> Self {
+        let mut bindings = Self {
+            binds: vec![],
+            held: vec![],
+            dead_zone: DEFAULT_DEAD_ZONE,
+        };
+        let lines = config
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'));
+        for line in lines {
+            let Some((name, inputs)) = line.split_once('=') else {
+                warn!("Skipping binding line {line:?}");
+                continue;
+            };
+            let name = name.trim();
+            if name == "dead_zone" {
+                match inputs.trim().parse::<i16>() {
+                    Ok(dead_zone) => bindings.dead_zone = dead_zone.max(0),
+                    Err(_) => warn!("Invalid dead zone {inputs:?}"),
+                }
+                continue;
+            }
+            let Some(button) = parse_button(name) else {
+                warn!("Unknown Game Boy button {name:?}");
+                continue;
+            };
+            for input in inputs.split(',').map(str::trim) {
+                match parse_input(input) {
+                    Some(Input::Key(key)) if hotkey(key).is_some() => {
+                        warn!("{input} is a hotkey, it can't be bound to {name}")
+                    }
+                    Some(input) => bindings.binds.push((input, button)),
+                    None => warn!("Unknown input {input:?} for {name}"),
+                }
+            }
+        }
+        bindings.held = vec![false; bindings.binds.len()];
+        bindings
+    }
+
+    /// Buttons pressed or released by `keycode` going down or up
+    pub fn key(&mut self, keycode: Keycode, is_down: bool) -> Vec<(Button, bool)> {
+        self.update(|input| (input == Input::Key(keycode)).then_some(is_down))
+    }
+
+    /// Buttons pressed or released by `pad_button` going down or up
+    pub fn pad_button(&mut self, pad_button: PadButton, is_down: bool) -> Vec<(Button, bool)> {
+        self.update(|input| (input == Input::Pad(pad_button)).then_some(is_down))
+    }
+
+    /// Buttons pressed or released by `axis` moving to `value`
+    pub fn axis(&mut self, axis: Axis, value: i16) -> Vec<(Button, bool)> {
+        let dead_zone = self.dead_zone;
+        self.update(|input| match input {
+            Input::Axis(bound, true) if bound == axis => Some(value > dead_zone),
+            Input::Axis(bound, false) if bound == axis => Some(value < -dead_zone),
+            _ => None,
+        })
+    }
+
+    /// Sets every bind `held_by` has a state for, and reports the buttons that changed. Only
+    /// changes are reported, an input doesn't release a button another one is still holding
+    fn update(&mut self, held_by: impl Fn(Input) -> Option<bool>) -> Vec<(Button, bool)> {
+        let mut changes = vec![];
+        for index in 0..self.binds.len() {
+            let (input, button) = self.binds[index];
+            let Some(held) = held_by(input) else {
+                continue;
+            };
+            let was_pressed = self.is_pressed(button);
+            self.held[index] = held;
+            let pressed = self.is_pressed(button);
+            if pressed != was_pressed {
+                changes.push((button, pressed));
+            }
+        }
+        changes
+    }
+
+    fn is_pressed(&self, button: Button) -> bool {
+        self.binds
+            .iter()
+            .zip(&self.held)
+            .any(|(&(_, bound), &held)| bound == button && held)
+    }
+}
+
+fn config_path() -> PathBuf {
+    let dir = dirs::config_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("com.copticfelo.redgb");
+    std::fs::create_dir_all(&dir).ok();
+    dir.join("bindings.cfg")
+}
+
+fn parse_button(name: &str) -> Option<Button> {
+    match name.to_ascii_lowercase().as_str() {
+        "a" => Some(Button::A),
+        "b" => Some(Button::B),
+        "select" => Some(Button::Select),
+        "start" => Some(Button::Start),
+        "up" => Some(Button::Up),
+        "down" => Some(Button::Down),
+        "left" => Some(Button::Left),
+        "right" => Some(Button::Right),
+        _ => None,
+    }
+}
+
+fn parse_input(input: &str) -> Option<Input> {
+    let (kind, name) = input.split_once(':')?;
+    match kind.trim() {
+        "key" => Keycode::from_name(name.trim()).map(Input::Key),
+        "pad" => PadButton::from_string(name.trim()).map(Input::Pad),
+        "axis" => {
+            let name = name.trim();
+            let positive = match name.chars().last()? {
+                '+' => true,
+                '-' => false,
+                _ => return None,
+            };
+            Axis::from_string(&name[..name.len() - 1]).map(|axis| Input::Axis(axis, positive))
+        }
+        _ => None,
+    }
+}
+
+#[test]
+fn bindings_test() {
+    let mut bindings = Bindings::parse(
+        "a = key:Z, pad:a, key:F5\nleft = axis:leftx-, bogus\nright = axis:leftx+\ndead_zone = 100\n",
+    );
+    assert_eq!(bindings.dead_zone, 100);
+    assert_eq!(bindings.key(Keycode::Z, true), [(Button::A, true)]);
+    assert_eq!(bindings.key(Keycode::Z, true), []);
+    assert_eq!(bindings.key(Keycode::F5, true), []);
+    assert_eq!(hotkey(Keycode::F5), Some(Hotkey::SaveState));
+    // A stays down until both the key and the pad button let go
+    assert_eq!(bindings.pad_button(PadButton::South, true), []);
+    assert_eq!(bindings.key(Keycode::Z, false), []);
+    assert_eq!(
+        bindings.pad_button(PadButton::South, false),
+        [(Button::A, false)]
+    );
+    assert_eq!(bindings.axis(Axis::LeftX, 50), []);
+    assert_eq!(bindings.axis(Axis::LeftX, -200), [(Button::Left, true)]);
+    assert_eq!(bindings.axis(Axis::LeftX, -300), []);
+    assert_eq!(bindings.axis(Axis::LeftX, 20), [(Button::Left, false)]);
+    // Noise around the center doesn't release buttons the stick never pressed
+    assert_eq!(bindings.axis(Axis::LeftX, -20), []);
+    assert_eq!(bindings.axis(Axis::LeftX, 500), [(Button::Right, true)]);
+    assert_eq!(bindings.axis(Axis::LeftY, 500), []);
+}
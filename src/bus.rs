@@ -1,51 +1,91 @@
-use std::{sync::Arc, time::Instant};
+use std::io::Write;
 
-use log::debug;
-use ringbuf::{SharedRb, storage::Heap, wrap::caching::Caching};
+use log::{debug, error};
 
 use crate::{
     apu::{apu::APU, channel::AudioChannel},
-    cpu::{alu, input::Joypad, reg_file::RegFile, timer::GBTimer},
+    cdl::{self, Cdl},
+    cpu::{alu, input::Joypad, reg_file::RegFile, timer::GBTimer, trace},
+    debugger::{WatchHit, Watchpoint},
     error::GBError,
     mbc::mbc3::MBC3,
-    mem::map::Memory,
+    mem::{hdma::Hdma, map::Memory},
     ppu::ppu::PPU,
+    profiler::Profiler,
+    serial::Serial,
+    state::{SaveState, StateReader, StateWriter},
+    symbols::Symbols,
 };
 
-const SB: usize = 0x1;
-const SC: usize = 0x2;
+const KEY1: usize = 0x4D;
 
 pub struct Bus {
     pub registers: RegFile,
     pub memory: Memory,
     pub t_cycles: u64,
+    /// Clock of the PPU, APU and RTC, runs at half the CPU rate in double speed
+    ppu_cycles: u64,
+    pub double_speed: bool,
+    pub(crate) hdma: Hdma,
     pub ppu: PPU,
-    gbtimer: GBTimer,
+    pub(crate) gbtimer: GBTimer,
     pub serial_message: Vec<u8>,
+    pub serial: Serial,
     pub joypad: Joypad,
     pub apu: APU,
+    pub watchpoints: Vec<Watchpoint>,
+    pub watch_hit: Option<WatchHit>,
+    /// gameboy-doctor trace output, see [`trace`]
+    pub trace: Option<Box<dyn Write + Send>>,
+    /// Append the label of PC to trace lines, they can't be diffed with gameboy-doctor then
+    pub trace_labels: bool,
+    /// RGBDS labels for the debugger and traces
+    pub symbols: Symbols,
+    /// Code/Data Logger, see [`cdl`]
+    pub cdl: Option<Cdl>,
+    /// Cycle profiler, see [`crate::profiler`]
+    pub profiler: Option<Profiler>,
 }
 
 impl Bus {
-    pub fn init(
-        registers: RegFile,
-        memory: Memory,
-        ppu: PPU,
-        buffer: Caching<Arc<SharedRb<Heap<f32>>>, true, false>,
-    ) -> Self {
+    pub fn init(registers: RegFile, memory: Memory, ppu: PPU) -> Self {
         Self {
             registers,
             memory,
             t_cycles: 0,
+            ppu_cycles: 0,
+            double_speed: false,
+            hdma: Hdma::default(),
             ppu,
             gbtimer: GBTimer::default(),
             serial_message: vec![],
+            serial: Serial::default(),
             joypad: Joypad::default(),
-            apu: APU::new(buffer),
+            apu: APU::new(),
+            watchpoints: vec![],
+            watch_hit: None,
+            trace: None,
+            trace_labels: false,
+            symbols: Symbols::default(),
+            cdl: None,
+            profiler: None,
         }
     }
+    /// Fetches an operand byte at PC
     pub fn fetch(&mut self) -> u8 {
-        let result = match self.read(self.registers.pc) {
+        self.fetch_as(cdl::OPERAND)
+    }
+    /// Fetches the first byte of an instruction at PC
+    pub fn fetch_opcode(&mut self) -> u8 {
+        self.fetch_as(cdl::CODE)
+    }
+    fn fetch_as(&mut self, cdl_flag: u8) -> u8 {
+        // Opcode fetches don't go through read() so they can't trip read watchpoints
+        self.tick();
+        if let Some(cdl) = self.cdl.as_mut() {
+            cdl.log(&self.memory, self.registers.pc, cdl_flag);
+        }
+        let result = match self.memory.dma_read(self.registers.pc as usize) {
             Ok(op) => op,
             // HACK: Probably improper error handling
             Err(s) => {
@@ -59,32 +99,124 @@ impl Bus {
 
     pub fn tick(&mut self) {
         self.t_cycles += 4_u64;
-        self.ppu.tick(&mut self.memory, &self.t_cycles);
+        self.ppu_cycles += if self.double_speed { 2 } else { 4 };
+        let ppu_step = self.ppu_cycles.is_multiple_of(4);
+        if ppu_step {
+            self.ppu.tick(&mut self.memory, &self.ppu_cycles);
+        }
         self.gbtimer.tick(&mut self.memory, &self.t_cycles);
-        self.apu.tick(&self.memory);
-
-        if let Some(mbc3) = self.memory.controller.as_any().downcast_mut::<MBC3>() {
-            mbc3.rtc.tick(&self.t_cycles);
+        if ppu_step {
+            self.apu.tick(&self.memory);
+            if let Some(mbc3) = self.memory.controller.as_any().downcast_mut::<MBC3>() {
+                mbc3.rtc.tick(&self.ppu_cycles);
+            }
         }
-
-        if alu::read_bits(self.memory.io[SC], 7, 1) == 1
-            && alu::read_bits(self.memory.io[SC], 0, 1) == 1
-        {
-            self.memory.io[SB] = 0xFF;
-            self.memory.io[SC] = alu::set_bit(self.memory.io[SC], 7, false);
-            self.memory.io[0xF] = alu::set_bit(self.memory.io[0xF], 3, true);
+        if self.ppu.hblank_flag {
+            self.ppu.hblank_flag = false;
+            if self.hdma.active {
+                self.hdma_copy(1);
+            }
+        }
+        if self.ppu.vblank_flag {
+            self.ppu.vblank_flag = false;
+            self.memory.apply_cheats();
         }
+
+        self.serial.tick(&mut self.memory);
     }
     pub fn read(&mut self, addr: u16) -> Result<u8, GBError> {
         self.tick();
-        self.memory.dma_read(addr as usize)
+        let value = match addr {
+            0xFF44 if self.trace.is_some() => trace::STUB_LY,
+            _ => self.memory.dma_read(addr as usize)?,
+        };
+        if let Some(cdl) = self.cdl.as_mut() {
+            cdl.log(&self.memory, addr, cdl::DATA);
+        }
+        if !self.watchpoints.is_empty() {
+            self.check_watchpoints(addr, value, false);
+        }
+        Ok(value)
     }
     pub fn write(&mut self, addr: u16, value: u8) -> Result<(), GBError> {
         self.tick();
+        if !self.watchpoints.is_empty() {
+            self.check_watchpoints(addr, value, true);
+        }
         self.memory.dma_write(addr as usize, value)?;
         self.handle_io(addr as usize, value)?;
         Ok(())
     }
+    /// Runs `blocks` HDMA blocks, the CPU is stalled for 8 M-cycles (16 in double speed) each
+    fn hdma_copy(&mut self, blocks: u8) {
+        for _ in 0..blocks {
+            if let Some(cdl) = self.cdl.as_mut() {
+                for i in 0..0x10 {
+                    cdl.log(&self.memory, self.hdma.src.wrapping_add(i), cdl::DMA);
+                }
+            }
+            if let Err(e) = self.hdma.copy_block(&mut self.memory) {
+                debug!("{}", e);
+            }
+            for _ in 0..if self.double_speed { 16 } else { 8 } {
+                self.tick();
+            }
+        }
+    }
+    /// Interrupts both requested in IF and enabled in IE
+    pub fn pending_interrupts(&self) -> u8 {
+        self.memory.io[0x0F] & self.memory.ie & 0x1F
+    }
+    /// One M-cycle in STOP mode, nothing runs but the frame still ends every 70224 T-cycles so
+    /// the frontend keeps polling the buttons that wake the CPU up
+    pub fn stopped_tick(&mut self) {
+        self.t_cycles += 4;
+        if self.t_cycles.is_multiple_of(70224) {
+            self.ppu.frame_flag = true;
+        }
+    }
+    /// STOP with KEY1 bit 0 set toggles CGB double speed
+    pub fn switch_speed(&mut self) -> bool {
+        if !self.memory.cgb || self.memory.io[KEY1] & 1 == 0 {
+            return false;
+        }
+        self.double_speed = !self.double_speed;
+        self.memory.io[KEY1] = 0x7E | (self.double_speed as u8) << 7;
+        self.ppu_cycles &= !3;
+        true
+    }
+    /// Writes the trace line for the instruction at PC, tracing stops on the first I/O error
+    pub fn trace_instruction(&mut self) {
+        if self.trace.is_none() {
+            return;
+        }
+        let line = if self.trace_labels {
+            trace::line(self)
+        } else {
+            trace::doctor_line(self)
+        };
+        if let Some(out) = self.trace.as_mut()
+            && let Err(e) = writeln!(out, "{line}")
+        {
+            error!("Trace stopped: {e}");
+            self.trace = None;
+        }
+    }
+    fn check_watchpoints(&mut self, addr: u16, value: u8, is_write: bool) {
+        if self.watch_hit.is_none()
+            && let Some(index) = self
+                .watchpoints
+                .iter()
+                .position(|watch| watch.matches(addr, is_write))
+        {
+            self.watch_hit = Some(WatchHit {
+                index,
+                addr,
+                value,
+                is_write,
+            });
+        }
+    }
     fn handle_io(&mut self, addr: usize, value: u8) -> Result<(), GBError> {
         match addr {
             0xFF00 => {
@@ -98,6 +230,7 @@ impl Bus {
             0xFF01 => {
                 self.serial_message.push(value);
             }
+            0xFF02 => self.serial.control_written(&self.memory),
             0xFF04 => {
                 let byte = self.memory.io.get_mut(addr - 0xFF00);
                 *byte.unwrap() = 0;
@@ -105,28 +238,29 @@ impl Bus {
             }
             0xFF11 => self.apu.pulse_1.length_timer = 64 - alu::read_bits(value, 0, 6),
             0xFF16 => self.apu.pulse_2.length_timer = 64 - alu::read_bits(value, 0, 6),
-            0xFF14 => {
-                if alu::read_bits(value, 7, 1) == 1 {
-                    self.apu
-                        .pulse_1
-                        .reset(self.memory.io[0x12], self.memory.io[0x13], value);
-                }
+            0xFF14 if alu::read_bits(value, 7, 1) == 1 => {
+                self.apu
+                    .pulse_1
+                    .reset(self.memory.io[0x12], self.memory.io[0x13], value);
             }
-            0xFF19 => {
-                if alu::read_bits(value, 7, 1) == 1 {
-                    self.apu
-                        .pulse_2
-                        .reset(self.memory.io[0x17], self.memory.io[0x18], value);
-                }
+            0xFF19 if alu::read_bits(value, 7, 1) == 1 => {
+                self.apu
+                    .pulse_2
+                    .reset(self.memory.io[0x17], self.memory.io[0x18], value);
             }
             0xFF1A => self.apu.wave.dac_enable = alu::read_bits(value, 7, 1) == 1,
             0xFF1B => self.apu.wave.length_timer = 256 - value as u16,
-            0xFF1E => {
-                if alu::read_bits(value, 7, 1) == 1 {
-                    self.apu
-                        .wave
-                        .reset(self.memory.io[0x1C], self.memory.io[0x1D], value);
-                }
+            0xFF1E if alu::read_bits(value, 7, 1) == 1 => {
+                self.apu
+                    .wave
+                    .reset(self.memory.io[0x1C], self.memory.io[0x1D], value);
+            }
+            0xFF20 => self.apu.noise.length_timer = 64 - alu::read_bits(value, 0, 6),
+            0xFF22 => self.apu.noise.read_polynomial(value),
+            0xFF23 if alu::read_bits(value, 7, 1) == 1 => {
+                self.apu
+                    .noise
+                    .reset(self.memory.io[0x21], self.memory.io[0x22], value);
             }
             0xFF30..0xFF40 => self
                 .apu
@@ -135,8 +269,47 @@ impl Bus {
             0xFF46 => {
                 Memory::oam_transfer(self, value);
             }
+            0xFF4D => {
+                self.memory.io[KEY1] = if self.memory.cgb {
+                    0x7E | (self.double_speed as u8) << 7 | (value & 1)
+                } else {
+                    0xFF
+                };
+            }
+            0xFF4F => self.memory.set_vram_bank(value),
+            0xFF50 if value != 0 => self.memory.boot_rom_mapped = false,
+            0xFF51..=0xFF55 if self.memory.cgb => {
+                let blocks = self.hdma.write(&mut self.memory, addr, value);
+                self.hdma_copy(blocks);
+            }
+            0xFF68..=0xFF6B if self.memory.cgb => self.memory.write_palette(addr, value),
+            0xFF70 => self.memory.set_wram_bank(value),
             _ => (),
         };
         Ok(())
     }
 }
+
+impl SaveState for Bus {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.u64(self.t_cycles);
+        w.u64(self.ppu_cycles);
+        w.bool(self.double_speed);
+        self.hdma.save_state(w);
+        self.serial.save_state(w);
+    }
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), GBError> {
+        self.t_cycles = r.u64()?;
+        if r.version >= 3 {
+            self.ppu_cycles = r.u64()?;
+            self.double_speed = r.bool()?;
+            self.hdma.load_state(r)?;
+        } else {
+            self.ppu_cycles = self.t_cycles;
+        }
+        if r.version >= 5 {
+            self.serial.load_state(r)?;
+        }
+        Ok(())
+    }
+}
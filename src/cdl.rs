@@ -0,0 +1,186 @@
+//! Code/Data Logger
+//!
+//! Records how every ROM byte was used while playing, to tell code from data when
+//! disassembling. The `.cdl` file has no header, it's one flag byte per ROM byte in file order,
+//! so the bank of a byte is its offset / 0x4000:
+//!
+//! | Bit  | Meaning |
+//! | ---- | ------- |
+//! | 0x01 | executed as the first byte of an instruction |
+//! | 0x02 | read as an operand (immediates, the second byte of `CB` opcodes) |
+//! | 0x04 | read as data |
+//! | 0x08 | copied by OAM DMA or CGB VRAM DMA |
+//! | 0x10 | accessed while mapped at 0x0000-0x3FFF |
+//! | 0x20 | accessed while mapped at 0x4000-0x7FFF |
+//! | 0x40 | target of a taken JP, JR or `jp hl` |
+//! | 0x80 | target of a CALL or RST |
+//!
+//! Bits only ever get set, logging can be resumed from an earlier file.
+//!
+//! Next to it goes a lossy export in Mesen 2's `CDLv2` format, for its debugger and the tools that
+//! read its logs: the `CDLv2` magic, the CRC32 of the ROM as a little endian u32, then one byte
+//! per ROM byte with 0x01 code (opcode or operand), 0x02 data (DMA sources included), 0x04 jump
+//! target and 0x08 subroutine entry point.
+
+use std::path::{Path, PathBuf};
+
+use crate::{error::GBError, mem::map::Memory};
+
+pub const CODE: u8 = 0x01;
+pub const OPERAND: u8 = 0x02;
+pub const DATA: u8 = 0x04;
+pub const DMA: u8 = 0x08;
+pub const LOW_WINDOW: u8 = 0x10;
+pub const HIGH_WINDOW: u8 = 0x20;
+pub const JUMP_TARGET: u8 = 0x40;
+pub const SUB_ENTRY_POINT: u8 = 0x80;
+
+const MESEN_MAGIC: &[u8; 5] = b"CDLv2";
+const MESEN_CODE: u8 = 0x01;
+const MESEN_DATA: u8 = 0x02;
+const MESEN_JUMP_TARGET: u8 = 0x04;
+const MESEN_SUB_ENTRY_POINT: u8 = 0x08;
+
+pub struct Cdl {
+    rom_crc: u32,
+    flags: Vec<u8>,
+}
+
+impl Cdl {
+    pub fn new(rom: &[u8]) -> Self {
+        Self {
+            rom_crc: crc32(rom),
+            flags: vec![0; rom.len()],
+        }
+    }
+
+    /// Resumes logging from the file at `path`, or starts over if it doesn't exist or was made
+    /// for a ROM of a different size
+    pub fn load(path: &Path, rom: &[u8]) -> Self {
+        let mut cdl = Self::new(rom);
+        if let Ok(flags) = std::fs::read(path)
+            && flags.len() == rom.len()
+        {
+            cdl.flags = flags;
+        }
+        cdl
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), GBError> {
+        std::fs::write(path, &self.flags).map_err(|_| GBError::SaveError)
+    }
+
+    pub fn export_mesen(&self, path: &Path) -> Result<(), GBError> {
+        std::fs::write(path, self.to_mesen()).map_err(|_| GBError::SaveError)
+    }
+
+    /// The log in `CDLv2` format, operands become code and DMA sources data, the banks are lost
+    pub fn to_mesen(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(MESEN_MAGIC.len() + 4 + self.flags.len());
+        bytes.extend_from_slice(MESEN_MAGIC);
+        bytes.extend_from_slice(&self.rom_crc.to_le_bytes());
+        bytes.extend(self.flags.iter().map(|&flags| {
+            [
+                (CODE | OPERAND, MESEN_CODE),
+                (DATA | DMA, MESEN_DATA),
+                (JUMP_TARGET, MESEN_JUMP_TARGET),
+                (SUB_ENTRY_POINT, MESEN_SUB_ENTRY_POINT),
+            ]
+            .iter()
+            .filter(|&&(ours, _)| flags & ours != 0)
+            .fold(0, |mesen, &(_, bit)| mesen | bit)
+        }));
+        bytes
+    }
+
+    pub fn flags(&self) -> &[u8] {
+        &self.flags
+    }
+
+    /// Marks the ROM byte `addr` is currently served from, does nothing outside of ROM
+    pub fn log(&mut self, mem: &Memory, addr: u16, flag: u8) {
+        let window = if addr < 0x4000 {
+            LOW_WINDOW
+        } else {
+            HIGH_WINDOW
+        };
+        if let Some(offset) = mem.rom_offset(addr)
+            && let Some(flags) = self.flags.get_mut(offset)
+        {
+            *flags |= flag | window;
+        }
+    }
+
+    /// Bytes seen as code (opcodes or operands), as data only, and never touched
+    pub fn coverage(&self) -> (usize, usize, usize) {
+        self.flags
+            .iter()
+            .fold((0, 0, 0), |(code, data, unused), &flags| {
+                if flags & (CODE | OPERAND) != 0 {
+                    (code + 1, data, unused)
+                } else if flags & (DATA | DMA) != 0 {
+                    (code, data + 1, unused)
+                } else {
+                    (code, data, unused + 1)
+                }
+            })
+    }
+}
+
+/// Where the Mesen export of the log at `path` goes, `game.cdl` becomes `game.mesen.cdl`
+pub fn mesen_path(path: &Path) -> PathBuf {
+    path.with_extension("mesen.cdl")
+}
+
+/// CRC-32 (ISO-HDLC, as in zip and png)
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0, |crc, &byte| {
+        (0..8).fold(crc ^ byte as u32, |crc, _| {
+            (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg())
+        })
+    })
+}
+
+#[test]
+fn cdl_test() {
+    use crate::{gameboy::GameBoy, rom::rom_info::ROMInfo};
+    assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    let header = ROMInfo {
+        cartridge_type: 1,
+        rom_banks: 4,
+        ..GameBoy::test_header()
+    };
+    let mut rom = vec![0; 0x10000];
+    // ld a, 2 | ld [0x2000], a | ld a, [0x4000] | call 0x0110 | ld a, 0x40 | ldh [0x46], a | jr -2
+    rom[0x100..0x10F].copy_from_slice(&[
+        0x3E, 0x02, 0xEA, 0x00, 0x20, 0xFA, 0x00, 0x40, 0xCD, 0x10, 0x01, 0x00, 0x00, 0x00, 0x00,
+    ]);
+    rom[0x110..0x116].copy_from_slice(&[0x3E, 0x40, 0xE0, 0x46, 0x18, 0xFE]);
+    let mut gameboy = GameBoy::new(rom.clone(), header);
+    gameboy.bus.cdl = Some(Cdl::new(&rom));
+    gameboy.run_frame().unwrap();
+    let cdl = gameboy.bus.cdl.as_ref().unwrap();
+    let flags = cdl.flags();
+    assert_eq!(flags[0x100], CODE | LOW_WINDOW);
+    assert_eq!(flags[0x101], OPERAND | LOW_WINDOW);
+    assert_eq!(flags[0x108], CODE | LOW_WINDOW);
+    assert_eq!(flags[0x110], CODE | SUB_ENTRY_POINT | LOW_WINDOW);
+    assert_eq!(flags[0x114], CODE | JUMP_TARGET | LOW_WINDOW);
+    assert_eq!(flags[0x115], OPERAND | LOW_WINDOW);
+    assert_eq!(flags[0x8000], DATA | DMA | HIGH_WINDOW);
+    assert_eq!(flags[0x809F], DMA | HIGH_WINDOW);
+    assert_eq!(flags[0x80A0], 0);
+    assert_eq!(flags[0x4000], 0);
+    assert_eq!(cdl.coverage(), (17, 160, 0x10000 - 177));
+
+    let mesen = cdl.to_mesen();
+    assert_eq!(&mesen[..5], b"CDLv2");
+    assert_eq!(mesen[5..9], crc32(&rom).to_le_bytes());
+    let mesen = &mesen[9..];
+    assert_eq!(mesen.len(), 0x10000);
+    assert_eq!((mesen[0x100], mesen[0x101]), (MESEN_CODE, MESEN_CODE));
+    assert_eq!(mesen[0x110], MESEN_CODE | MESEN_SUB_ENTRY_POINT);
+    assert_eq!(mesen[0x114], MESEN_CODE | MESEN_JUMP_TARGET);
+    assert_eq!((mesen[0x8000], mesen[0x809F]), (MESEN_DATA, MESEN_DATA));
+    assert_eq!(mesen[0x4000], 0);
+}
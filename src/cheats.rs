@@ -0,0 +1,265 @@
+//! GameShark and Game Genie cheats
+//!
+//! - GameShark `01VVAAAA` codes write VV to RAM address AAAA (stored little endian) on every
+//!   VBlank, the `01` is the cartridge RAM bank and is ignored
+//! - Game Genie `ABC-DEF` and `ABC-DEF-GHI` codes patch ROM reads at address `FCDE ^ F000` to AB,
+//!   the 9 digit form only when the ROM holds the compare value encoded in G and I
+//!
+//! The list for a game lives next to its battery save as `<title>.cht`, one cheat per line:
+//! `+` or `-` for enabled, the code, then an optional description.
+
+use std::path::{Path, PathBuf};
+
+use crate::{error::GBError, mbc, rom::rom_info::ROMInfo};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheatKind {
+    GameShark {
+        addr: u16,
+        value: u8,
+    },
+    GameGenie {
+        addr: u16,
+        value: u8,
+        compare: Option<u8>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cheat {
+    pub code: String,
+    pub description: String,
+    pub enabled: bool,
+    pub kind: CheatKind,
+}
+
+impl Cheat {
+    pub fn parse(code: &str, description: &str) -> Result<Self, GBError> {
+        let invalid = || GBError::InvalidCheat(code.to_string());
+        let digits: String = code.chars().filter(|&c| c != '-').collect();
+        if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(invalid());
+        }
+        let hex = |range: std::ops::Range<usize>| {
+            digits
+                .get(range)
+                .and_then(|part| u16::from_str_radix(part, 16).ok())
+                .ok_or_else(invalid)
+        };
+        let kind = match digits.len() {
+            8 if !code.contains('-') => {
+                let addr = hex(4..6)? | hex(6..8)? << 8;
+                // Only RAM, writing below 0x8000 would hit the MBC registers
+                if addr < 0x8000 {
+                    return Err(invalid());
+                }
+                CheatKind::GameShark {
+                    addr,
+                    value: hex(2..4)? as u8,
+                }
+            }
+            6 | 9 => {
+                let addr = (hex(5..6)? ^ 0xF) << 12 | hex(2..5)?;
+                if addr >= 0x8000 {
+                    return Err(invalid());
+                }
+                let compare = if digits.len() == 9 {
+                    let encoded = (hex(6..7)? << 4 | hex(8..9)?) as u8;
+                    Some(encoded.rotate_right(2) ^ 0xBA)
+                } else {
+                    None
+                };
+                CheatKind::GameGenie {
+                    addr,
+                    value: hex(0..2)? as u8,
+                    compare,
+                }
+            }
+            _ => return Err(invalid()),
+        };
+        Ok(Self {
+            code: code.to_uppercase(),
+            description: description.to_string(),
+            enabled: true,
+            kind,
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Cheats {
+    list: Vec<Cheat>,
+    /// Enabled Game Genie patches, (addr, value, compare), checked on every ROM read
+    rom_patches: Vec<(u16, u8, Option<u8>)>,
+}
+
+impl Cheats {
+    pub fn list(&self) -> &[Cheat] {
+        &self.list
+    }
+
+    pub fn add(&mut self, cheat: Cheat) -> usize {
+        self.list.push(cheat);
+        self.update_patches();
+        self.list.len() - 1
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<Cheat> {
+        let cheat = (index < self.list.len()).then(|| self.list.remove(index));
+        self.update_patches();
+        cheat
+    }
+
+    /// Returns false if there's no cheat at `index`
+    pub fn set_enabled(&mut self, index: usize, enabled: bool) -> bool {
+        let Some(cheat) = self.list.get_mut(index) else {
+            return false;
+        };
+        cheat.enabled = enabled;
+        self.update_patches();
+        true
+    }
+
+    fn update_patches(&mut self) {
+        self.rom_patches = self
+            .list
+            .iter()
+            .filter(|cheat| cheat.enabled)
+            .filter_map(|cheat| match cheat.kind {
+                CheatKind::GameGenie {
+                    addr,
+                    value,
+                    compare,
+                } => Some((addr, value, compare)),
+                CheatKind::GameShark { .. } => None,
+            })
+            .collect();
+    }
+
+    /// `value` as read from ROM at `addr`, after Game Genie patches
+    pub fn patch_rom(&self, addr: u16, value: u8) -> u8 {
+        self.rom_patches
+            .iter()
+            .find(|&&(patch_addr, _, compare)| {
+                patch_addr == addr && compare.is_none_or(|compare| compare == value)
+            })
+            .map_or(value, |&(_, patched, _)| patched)
+    }
+
+    /// The enabled GameShark writes, (addr, value)
+    pub fn ram_writes(&self) -> impl Iterator<Item = (u16, u8)> + '_ {
+        self.list
+            .iter()
+            .filter(|cheat| cheat.enabled)
+            .filter_map(|cheat| match cheat.kind {
+                CheatKind::GameShark { addr, value } => Some((addr, value)),
+                CheatKind::GameGenie { .. } => None,
+            })
+    }
+
+    /// Parses a cheat file, broken lines are skipped
+    pub fn parse(text: &str) -> Self {
+        let mut cheats = Self::default();
+        // Notepad starts UTF-8 files with a BOM
+        let text = text.strip_prefix('\u{FEFF}').unwrap_or(text);
+        for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            let (enabled, rest) = if let Some(rest) = line.strip_prefix('+') {
+                (true, rest.trim())
+            } else if let Some(rest) = line.strip_prefix('-') {
+                (false, rest.trim())
+            } else {
+                (true, line)
+            };
+            let (code, description) = rest.split_once(' ').unwrap_or((rest, ""));
+            match Cheat::parse(code, description.trim()) {
+                Ok(cheat) => {
+                    cheats.list.push(Cheat { enabled, ..cheat });
+                }
+                Err(e) => log::warn!("{e}"),
+            }
+        }
+        cheats.update_patches();
+        cheats
+    }
+
+    pub fn to_text(&self) -> String {
+        self.list
+            .iter()
+            .map(|cheat| {
+                let enabled = if cheat.enabled { '+' } else { '-' };
+                format!("{enabled} {} {}\n", cheat.code, cheat.description)
+            })
+            .collect()
+    }
+
+    /// Loads the cheats saved at `path`, no file means no cheats
+    pub fn load(path: &Path) -> Self {
+        match std::fs::read_to_string(path) {
+            Ok(text) => Self::parse(&text),
+            Err(_) => Self::default(),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), GBError> {
+        std::fs::write(path, self.to_text()).map_err(|_| GBError::SaveError)
+    }
+}
+
+/// `<title>.cht` next to the battery save
+pub fn cheat_path(rom_header: &ROMInfo) -> PathBuf {
+    mbc::save_path(rom_header).with_extension("cht")
+}
+
+#[test]
+fn cheats_test() {
+    let shark = Cheat::parse("01FF34C2", "").unwrap();
+    assert_eq!(
+        shark.kind,
+        CheatKind::GameShark {
+            addr: 0xC234,
+            value: 0xFF
+        }
+    );
+    // 0x4A50: replace 0x6D with 0x00
+    let genie = Cheat::parse("00A-50B-5CF", "").unwrap();
+    assert_eq!(
+        genie.kind,
+        CheatKind::GameGenie {
+            addr: 0x4A50,
+            value: 0x00,
+            compare: Some(0x6D)
+        }
+    );
+    assert!(Cheat::parse("01FF3412", "").is_err());
+    assert!(Cheat::parse("XYZ-123", "").is_err());
+
+    let mut cheats = Cheats::parse("+ 00A-50B-5CF Skip\n- 01FF34C2 Lives\ngarbage\n");
+    assert_eq!(cheats.list().len(), 2);
+    assert_eq!(cheats.patch_rom(0x4A50, 0x6D), 0x00);
+    assert_eq!(cheats.patch_rom(0x4A50, 0x6E), 0x6E);
+    assert_eq!(cheats.ram_writes().count(), 0);
+    cheats.set_enabled(1, true);
+    cheats.set_enabled(0, false);
+    assert_eq!(cheats.patch_rom(0x4A50, 0x6D), 0x6D);
+    assert_eq!(cheats.ram_writes().collect::<Vec<_>>(), [(0xC234, 0xFF)]);
+    assert_eq!(Cheats::parse(&cheats.to_text()).list(), cheats.list());
+    let bom = Cheats::parse("\u{FEFF}- 01FF34C2 Lives\n\u{e9}t\u{e9}\n");
+    assert_eq!(bom.list().len(), 1);
+    assert!(!bom.list()[0].enabled);
+
+    use crate::{gameboy::GameBoy, mem::map::Memory};
+    let mut gameboy = GameBoy::test_with_patches(&[
+        // ld a, [0x4A50] | ld [0xC000], a | jr -2
+        (0x100, &[0xFA, 0x50, 0x4A, 0xEA, 0x00, 0xC0, 0x18, 0xFE]),
+        (0x4A50, &[0x6D]),
+    ]);
+    gameboy.bus.memory.cheats = cheats;
+    gameboy.bus.memory.cheats.set_enabled(0, true);
+    gameboy.run_frame().unwrap();
+    let mem = &gameboy.bus.memory;
+    assert_eq!(mem.dma_read(0xC000).unwrap(), 0x00);
+    assert_eq!(mem.dma_read(0xC234).unwrap(), 0xFF);
+    // OAM DMA reads the MBC directly
+    Memory::oam_transfer(&mut gameboy.bus, 0x4A);
+    assert_eq!(gameboy.bus.memory.dma_read(0xFE50).unwrap(), 0x00);
+}
@@ -0,0 +1,210 @@
+//! Static SM83 disassembler, decodes straight from bytes without touching the [`Bus`]
+//!
+//! Mnemonics follow RGBDS syntax, relative jumps are shown as their absolute target.
+//!
+//! [`Bus`]: crate::bus::Bus
+
+const R8: [&str; 8] = ["b", "c", "d", "e", "h", "l", "[hl]", "a"];
+const R16: [&str; 4] = ["bc", "de", "hl", "sp"];
+const R16_STK: [&str; 4] = ["bc", "de", "hl", "af"];
+const R16_MEM: [&str; 4] = ["bc", "de", "hl+", "hl-"];
+const COND: [&str; 4] = ["nz", "z", "nc", "c"];
+const ALU: [&str; 8] = ["add", "adc", "sub", "sbc", "and", "xor", "or", "cp"];
+const ACC_OPS: [&str; 8] = ["rlca", "rrca", "rla", "rra", "daa", "cpl", "scf", "ccf"];
+const CB_OPS: [&str; 8] = ["rlc", "rrc", "rl", "rr", "sla", "sra", "swap", "srl"];
+
+/// A decoded instruction, `cycles` are T-cycles as listed in the Pan Docs opcode table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instruction {
+    pub addr: u16,
+    pub mnemonic: String,
+    pub len: u8,
+    /// Cycles when a conditional branch is not taken, or the only timing otherwise
+    pub cycles: u8,
+    /// Cycles when a conditional branch is taken
+    pub cycles_taken: Option<u8>,
+}
+
+impl Instruction {
+    fn new(addr: u16, mnemonic: String, len: u8, cycles: u8) -> Self {
+        Self {
+            addr,
+            mnemonic,
+            len,
+            cycles,
+            cycles_taken: None,
+        }
+    }
+    fn branch(addr: u16, mnemonic: String, len: u8, cycles: u8, taken: u8) -> Self {
+        Self {
+            cycles_taken: Some(taken),
+            ..Self::new(addr, mnemonic, len, cycles)
+        }
+    }
+}
+
+/// Decodes the instruction at the start of `bytes`, which is located at `addr`
+///
+/// Illegal opcodes, and instructions cut off by the end of `bytes`, come out as a single `db`,
+/// an empty slice gives a zero length `db`
+pub fn disassemble(bytes: &[u8], addr: u16) -> Instruction {
+    let Some(&opcode) = bytes.first() else {
+        return Instruction::new(addr, "db".to_string(), 0, 0);
+    };
+    let db = Instruction::new(addr, format!("db ${opcode:02X}"), 1, 4);
+    let n8 = match bytes.get(1) {
+        Some(&n8) => n8,
+        None if operand_len(opcode) > 0 => return db,
+        None => 0,
+    };
+    let n16 = match bytes.get(2) {
+        Some(&high) => u16::from_le_bytes([n8, high]),
+        None if operand_len(opcode) > 1 => return db,
+        None => 0,
+    };
+    let e8 = n8 as i8;
+    let x = opcode >> 6;
+    let y = ((opcode >> 3) & 7) as usize;
+    let z = (opcode & 7) as usize;
+    let p = y >> 1;
+    let q = y & 1;
+    // [hl] operands add a memory access to every r8 instruction
+    let hl_cost = |r: usize, cost: u8| if r == 6 { cost } else { 0 };
+    let ins = |mnemonic: String, len: u8, cycles: u8| Instruction::new(addr, mnemonic, len, cycles);
+    match (x, z) {
+        (0, 0) => match y {
+            0 => ins("nop".to_string(), 1, 4),
+            1 => ins(format!("ld [${n16:04X}], sp"), 3, 20),
+            2 => ins("stop".to_string(), 2, 4),
+            3 => ins(format!("jr ${:04X}", jr_target(addr, e8)), 2, 12),
+            _ => Instruction::branch(
+                addr,
+                format!("jr {}, ${:04X}", COND[y - 4], jr_target(addr, e8)),
+                2,
+                8,
+                12,
+            ),
+        },
+        (0, 1) if q == 0 => ins(format!("ld {}, ${n16:04X}", R16[p]), 3, 12),
+        (0, 1) => ins(format!("add hl, {}", R16[p]), 1, 8),
+        (0, 2) if q == 0 => ins(format!("ld [{}], a", R16_MEM[p]), 1, 8),
+        (0, 2) => ins(format!("ld a, [{}]", R16_MEM[p]), 1, 8),
+        (0, 3) => ins(
+            format!("{} {}", if q == 0 { "inc" } else { "dec" }, R16[p]),
+            1,
+            8,
+        ),
+        (0, 4) => ins(format!("inc {}", R8[y]), 1, 4 + hl_cost(y, 8)),
+        (0, 5) => ins(format!("dec {}", R8[y]), 1, 4 + hl_cost(y, 8)),
+        (0, 6) => ins(format!("ld {}, ${n8:02X}", R8[y]), 2, 8 + hl_cost(y, 4)),
+        (0, 7) => ins(ACC_OPS[y].to_string(), 1, 4),
+        (1, 6) if y == 6 => ins("halt".to_string(), 1, 4),
+        (1, _) => ins(
+            format!("ld {}, {}", R8[y], R8[z]),
+            1,
+            4 + hl_cost(y, 4) + hl_cost(z, 4),
+        ),
+        (2, _) => ins(format!("{} a, {}", ALU[y], R8[z]), 1, 4 + hl_cost(z, 4)),
+        (3, 0) => match y {
+            0..4 => Instruction::branch(addr, format!("ret {}", COND[y]), 1, 8, 20),
+            4 => ins(format!("ldh [${:04X}], a", 0xFF00 | n8 as u16), 2, 12),
+            5 => ins(format!("add sp, {e8}"), 2, 16),
+            6 => ins(format!("ldh a, [${:04X}]", 0xFF00 | n8 as u16), 2, 12),
+            _ => ins(format!("ld hl, sp{e8:+}"), 2, 12),
+        },
+        (3, 1) if q == 0 => ins(format!("pop {}", R16_STK[p]), 1, 12),
+        (3, 1) => match p {
+            0 => ins("ret".to_string(), 1, 16),
+            1 => ins("reti".to_string(), 1, 16),
+            2 => ins("jp hl".to_string(), 1, 4),
+            _ => ins("ld sp, hl".to_string(), 1, 8),
+        },
+        (3, 2) => match y {
+            0..4 => Instruction::branch(addr, format!("jp {}, ${n16:04X}", COND[y]), 3, 12, 16),
+            4 => ins("ldh [c], a".to_string(), 1, 8),
+            5 => ins(format!("ld [${n16:04X}], a"), 3, 16),
+            6 => ins("ldh a, [c]".to_string(), 1, 8),
+            _ => ins(format!("ld a, [${n16:04X}]"), 3, 16),
+        },
+        (3, 3) => match y {
+            0 => ins(format!("jp ${n16:04X}"), 3, 16),
+            1 => prefixed(addr, n8),
+            6 => ins("di".to_string(), 1, 4),
+            7 => ins("ei".to_string(), 1, 4),
+            _ => db,
+        },
+        (3, 4) if y < 4 => {
+            Instruction::branch(addr, format!("call {}, ${n16:04X}", COND[y]), 3, 12, 24)
+        }
+        (3, 5) if q == 0 => ins(format!("push {}", R16_STK[p]), 1, 16),
+        (3, 5) if p == 0 => ins(format!("call ${n16:04X}"), 3, 24),
+        (3, 6) => ins(format!("{} a, ${n8:02X}", ALU[y]), 2, 8),
+        (3, 7) => ins(format!("rst ${:02X}", y * 8), 1, 16),
+        _ => db,
+    }
+}
+
+fn prefixed(addr: u16, opcode: u8) -> Instruction {
+    let y = ((opcode >> 3) & 7) as usize;
+    let z = (opcode & 7) as usize;
+    let mnemonic = match opcode >> 6 {
+        0 => format!("{} {}", CB_OPS[y], R8[z]),
+        1 => format!("bit {y}, {}", R8[z]),
+        2 => format!("res {y}, {}", R8[z]),
+        _ => format!("set {y}, {}", R8[z]),
+    };
+    // bit only reads [hl], the rest read and write it back
+    let hl_cost = match (z, opcode >> 6) {
+        (6, 1) => 4,
+        (6, _) => 8,
+        _ => 0,
+    };
+    Instruction::new(addr, mnemonic, 2, 8 + hl_cost)
+}
+
+/// Number of operand bytes following `opcode`
+fn operand_len(opcode: u8) -> u8 {
+    match opcode {
+        0x01 | 0x11 | 0x21 | 0x31 | 0x08 | 0xC2 | 0xD2 | 0xCA | 0xDA | 0xC3 | 0xC4 | 0xD4
+        | 0xCC | 0xDC | 0xCD | 0xEA | 0xFA => 2,
+        0x10 | 0x18 | 0x20 | 0x28 | 0x30 | 0x38 | 0xCB | 0xE0 | 0xF0 | 0xE8 | 0xF8 => 1,
+        _ if opcode & 0xC7 == 0x06 || opcode & 0xC7 == 0xC6 => 1,
+        _ => 0,
+    }
+}
+
+fn jr_target(addr: u16, offset: i8) -> u16 {
+    addr.wrapping_add(2).wrapping_add(offset as u16)
+}
+
+#[test]
+fn disassemble_test() {
+    let dis = |bytes: &[u8]| disassemble(bytes, 0x150);
+    assert_eq!(dis(&[0x00]).mnemonic, "nop");
+    assert_eq!(dis(&[0x3E, 0x42]).mnemonic, "ld a, $42");
+    assert_eq!(dis(&[0xC3, 0x50, 0x01]).mnemonic, "jp $0150");
+    assert_eq!(dis(&[0x18, 0xFE]).mnemonic, "jr $0150");
+    assert_eq!(dis(&[0x2A]).mnemonic, "ld a, [hl+]");
+    assert_eq!(dis(&[0xE0, 0x44]).mnemonic, "ldh [$FF44], a");
+    assert_eq!(dis(&[0xF8, 0xFF]).mnemonic, "ld hl, sp-1");
+    assert_eq!(dis(&[0xCB, 0x7E]).mnemonic, "bit 7, [hl]");
+    assert_eq!(dis(&[0xCB, 0x37]).mnemonic, "swap a");
+    assert_eq!(dis(&[0xD3]).mnemonic, "db $D3");
+    assert_eq!(dis(&[0xCD, 0x00]).mnemonic, "db $CD");
+
+    let call = dis(&[0xC4, 0x00, 0x40]);
+    assert_eq!(
+        (call.len, call.cycles, call.cycles_taken),
+        (3, 12, Some(24))
+    );
+    assert_eq!(dis(&[0x34]).cycles, 12);
+    assert_eq!(dis(&[0xCB, 0x46]).cycles, 12);
+    assert_eq!(dis(&[0xCB, 0xC6]).cycles, 16);
+
+    for opcode in 0..=255 {
+        let ins = dis(&[opcode, 0, 0]);
+        let illegal = ins.mnemonic.starts_with("db");
+        assert!(illegal || ins.len == operand_len(opcode) + 1, "{opcode:#X}");
+        assert_eq!(dis(&[0xCB, opcode]).len, 2);
+    }
+}
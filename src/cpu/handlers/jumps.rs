@@ -1,4 +1,4 @@
-use crate::{bus::Bus, cpu::alu, error::GBError};
+use crate::{bus::Bus, cdl, cpu::alu, error::GBError};
 
 const CONDITION_NAMES: [&str; 4] = ["nz", "z", "nc", "c"];
 
@@ -23,6 +23,9 @@ pub fn jmp(bus: &mut Bus, opcode: u8, is_relative: bool) -> Result<String, GBErr
     log += &format!(" {:#X}", target_address);
     if bus.registers.match_condition(condition)? || !is_conditional {
         bus.registers.pc = target_address;
+        if let Some(cdl) = bus.cdl.as_mut() {
+            cdl.log(&bus.memory, target_address, cdl::JUMP_TARGET);
+        }
         bus.tick();
     }
     Ok(log)
@@ -43,6 +46,12 @@ pub fn call(bus: &mut Bus, opcode: u8) -> Result<String, GBError> {
         bus.registers.sp -= 1;
         bus.write(bus.registers.sp, (bus.registers.pc & 0xFF) as u8)?;
         bus.registers.pc = target_address;
+        if let Some(cdl) = bus.cdl.as_mut() {
+            cdl.log(&bus.memory, target_address, cdl::SUB_ENTRY_POINT);
+        }
+        if let Some(profiler) = bus.profiler.as_mut() {
+            profiler.call(&bus.memory, target_address, bus.registers.sp, false);
+        }
         bus.tick();
     }
     Ok(log)
@@ -72,6 +81,9 @@ pub fn ret(bus: &mut Bus, opcode: u8) -> Result<String, GBError> {
     let addr = alu::read_u16(&lsb, &msb);
     bus.tick();
     bus.registers.pc = addr;
+    if let Some(profiler) = bus.profiler.as_mut() {
+        profiler.ret(bus.registers.sp);
+    }
     Ok(log)
 }
 
@@ -83,5 +95,11 @@ pub fn rst(bus: &mut Bus, opcode: u8) -> Result<String, GBError> {
     bus.registers.sp -= 1;
     bus.write(bus.registers.sp, (bus.registers.pc & 0xFF) as u8)?;
     bus.registers.pc = addr;
+    if let Some(cdl) = bus.cdl.as_mut() {
+        cdl.log(&bus.memory, addr, cdl::SUB_ENTRY_POINT);
+    }
+    if let Some(profiler) = bus.profiler.as_mut() {
+        profiler.call(&bus.memory, addr, bus.registers.sp, false);
+    }
     Ok(format!("rst {:#X}", addr))
 }
@@ -1,6 +1,22 @@
-use sdl3::keyboard::Keycode;
+use crate::{
+    cpu::alu,
+    error::GBError,
+    mem::map::Memory,
+    state::{SaveState, StateReader, StateWriter},
+};
 
-use crate::{cpu::alu, mem::map::Memory};
+/// The eight DMG buttons
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Button {
+    A,
+    B,
+    Select,
+    Start,
+    Up,
+    Down,
+    Left,
+    Right,
+}
 
 #[derive(Default)]
 pub struct Joypad {
@@ -15,19 +31,24 @@ pub struct Joypad {
 }
 
 impl Joypad {
-    pub fn update(&mut self, keycode: Keycode, is_down: bool) {
-        match keycode {
-            Keycode::Z => self.a = is_down,
-            Keycode::X => self.b = is_down,
-            Keycode::C => self.select = is_down,
-            Keycode::Return => self.start = is_down,
-            Keycode::Up => self.up = is_down,
-            Keycode::Down => self.down = is_down,
-            Keycode::Left => self.left = is_down,
-            Keycode::Right => self.right = is_down,
-            _ => (),
+    pub fn update(&mut self, button: Button, is_down: bool) {
+        match button {
+            Button::A => self.a = is_down,
+            Button::B => self.b = is_down,
+            Button::Select => self.select = is_down,
+            Button::Start => self.start = is_down,
+            Button::Up => self.up = is_down,
+            Button::Down => self.down = is_down,
+            Button::Left => self.left = is_down,
+            Button::Right => self.right = is_down,
         }
     }
+    /// Whether a button of a group selected in `p1` is held, that's what wakes the CPU from STOP
+    pub fn selected_held(&self, p1: u8) -> bool {
+        let buttons = self.a || self.b || self.select || self.start;
+        let dpad = self.right || self.left || self.up || self.down;
+        (alu::read_bits(p1, 5, 1) == 0 && buttons) || (alu::read_bits(p1, 4, 1) == 0 && dpad)
+    }
     pub fn query_joypad(&mut self, mem: &mut Memory) {
         if alu::read_bits(mem.io[0], 5, 1) == 0 {
             mem.io[0] = alu::set_bit(mem.io[0], 0, !self.a);
@@ -45,3 +66,35 @@ impl Joypad {
         }
     }
 }
+
+impl SaveState for Joypad {
+    fn save_state(&self, w: &mut StateWriter) {
+        for button in [
+            self.a,
+            self.b,
+            self.select,
+            self.start,
+            self.up,
+            self.down,
+            self.left,
+            self.right,
+        ] {
+            w.bool(button);
+        }
+    }
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), GBError> {
+        for button in [
+            &mut self.a,
+            &mut self.b,
+            &mut self.select,
+            &mut self.start,
+            &mut self.up,
+            &mut self.down,
+            &mut self.left,
+            &mut self.right,
+        ] {
+            *button = r.bool()?;
+        }
+        Ok(())
+    }
+}
@@ -1,7 +1,9 @@
 pub mod alu;
+pub mod disasm;
 pub mod handlers;
 pub mod input;
 pub mod operands;
 pub mod reg_file;
 pub mod sm83;
 pub mod timer;
+pub mod trace;
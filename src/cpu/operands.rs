@@ -55,7 +55,7 @@ impl R8 {
 
     pub fn log(&self) -> String {
         match self {
-            Self::Register(reg) => format!("{}", REG_NAMES[*reg as usize]),
+            Self::Register(reg) => REG_NAMES[*reg as usize].to_string(),
             Self::Hl(addr) => format!("[{:#X}]", addr),
             Self::N8(n8) => format!("{:#X}", n8),
         }
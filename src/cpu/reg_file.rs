@@ -1,6 +1,11 @@
-use crate::{cpu::alu, error::GBError};
+use crate::{
+    cpu::alu,
+    error::GBError,
+    state::{SaveState, StateReader, StateWriter},
+};
 
 // TODO: Use Idiomatic rust names
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Modes {
     DMG,
     MGB,
@@ -43,9 +48,14 @@ pub struct RegFile {
     pub l: u8,
     pub sp: u16,
     pub pc: u16,
-    pub lfsr: u16,
     pub ime: bool,
+    /// Cleared by HALT and STOP
     pub exec: bool,
+    /// In STOP mode until a selected button is pressed, `exec` is cleared too
+    pub stopped: bool,
+    /// HALT was skipped with IME clear and an interrupt pending, the next opcode fetch doesn't
+    /// increment PC
+    pub halt_bug: bool,
 }
 
 impl RegFile {
@@ -67,9 +77,30 @@ impl RegFile {
             l,
             sp: 0xFFFE,
             pc: 0x100,
-            lfsr: u16::MAX,
             ime: false,
             exec: true,
+            stopped: false,
+            halt_bug: false,
+        }
+    }
+
+    /// Every register cleared, the state the boot ROM starts from
+    pub fn power_on() -> Self {
+        RegFile {
+            a: 0,
+            b: 0,
+            c: 0,
+            d: 0,
+            e: 0,
+            f: 0,
+            h: 0,
+            l: 0,
+            sp: 0,
+            pc: 0,
+            ime: false,
+            exec: true,
+            stopped: false,
+            halt_bug: false,
         }
     }
 
@@ -131,3 +162,46 @@ impl RegFile {
         Ok(())
     }
 }
+
+impl SaveState for RegFile {
+    fn save_state(&self, w: &mut StateWriter) {
+        for reg in [
+            self.a, self.b, self.c, self.d, self.e, self.f, self.h, self.l,
+        ] {
+            w.u8(reg);
+        }
+        w.u16(self.sp);
+        w.u16(self.pc);
+        w.bool(self.ime);
+        w.bool(self.exec);
+        w.bool(self.stopped);
+        w.bool(self.halt_bug);
+    }
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), GBError> {
+        for reg in [
+            &mut self.a,
+            &mut self.b,
+            &mut self.c,
+            &mut self.d,
+            &mut self.e,
+            &mut self.f,
+            &mut self.h,
+            &mut self.l,
+        ] {
+            *reg = r.u8()?;
+        }
+        self.sp = r.u16()?;
+        self.pc = r.u16()?;
+        if r.version < 2 {
+            // The noise LFSR used to live here, it's part of the APU chunk now
+            r.u16()?;
+        }
+        self.ime = r.bool()?;
+        self.exec = r.bool()?;
+        if r.version >= 6 {
+            self.stopped = r.bool()?;
+            self.halt_bug = r.bool()?;
+        }
+        Ok(())
+    }
+}
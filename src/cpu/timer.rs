@@ -1,6 +1,11 @@
 use log::trace;
 
-use crate::{bus::Bus, cpu::alu, mem::map::Memory};
+use crate::{
+    cpu::alu,
+    error::GBError,
+    mem::map::Memory,
+    state::{SaveState, StateReader, StateWriter},
+};
 
 const TIMA: usize = 0x05;
 const TMA: usize = 0x06;
@@ -47,3 +52,15 @@ impl GBTimer {
         }
     }
 }
+
+impl SaveState for GBTimer {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.u64(self.div_last);
+        w.u64(self.tima_last);
+    }
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), GBError> {
+        self.div_last = r.u64()?;
+        self.tima_last = r.u64()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,62 @@
+//! gameboy-doctor compatible CPU trace
+//!
+//! One line is written per executed instruction with the registers as they are before the
+//! opcode fetch, the output can be diffed against the reference logs from
+//! <https://github.com/robert/gameboy-doctor>. The tool expects LY to always read 0x90, the bus
+//! stubs it while a trace is active.
+
+use crate::bus::Bus;
+
+/// LY value reported to the CPU while tracing
+pub const STUB_LY: u8 = 0x90;
+
+/// [`doctor_line`] followed by the label of PC when symbols are loaded, e.g. `... (Main.loop+3)`.
+/// Only written with [`Bus::trace_labels`], gameboy-doctor can't compare those
+pub fn line(bus: &Bus) -> String {
+    let line = doctor_line(bus);
+    match bus
+        .symbols
+        .label(bus.registers.pc, bus.memory.controller.rom_bank())
+    {
+        Some(label) => format!("{line} ({label})"),
+        None => line,
+    }
+}
+
+/// Formats the current CPU state, e.g.
+/// `A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02`
+pub fn doctor_line(bus: &Bus) -> String {
+    let reg = &bus.registers;
+    let pcmem: Vec<String> = (0..4)
+        .map(|i| {
+            let byte = bus
+                .memory
+                .dma_read(reg.pc.wrapping_add(i) as usize)
+                .unwrap_or(0xFF);
+            format!("{byte:02X}")
+        })
+        .collect();
+    format!(
+        "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{}",
+        reg.a,
+        reg.f,
+        reg.b,
+        reg.c,
+        reg.d,
+        reg.e,
+        reg.h,
+        reg.l,
+        reg.sp,
+        reg.pc,
+        pcmem.join(",")
+    )
+}
+
+#[test]
+fn doctor_line_test() {
+    let gameboy = crate::gameboy::GameBoy::test_with_code(&[0x00, 0xC3, 0x13, 0x02]);
+    assert_eq!(
+        doctor_line(&gameboy.bus),
+        "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02"
+    );
+}
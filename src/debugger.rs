@@ -0,0 +1,653 @@
+//! Breakpoints, watchpoints and stepping on top of [`SM83::step_instruction`]
+//!
+//! The [`Debugger`] replaces [`SM83::step`] as the per-frame driver, it checks the stop
+//! conditions between instructions and pauses emulation until the next command. Commands are
+//! plain text lines so any frontend can feed it, the SDL frontend reads them from stdin when
+//! started with `--debug`.
+
+use std::io::Write;
+
+use crate::{
+    bus::Bus,
+    cheats::{self, Cheat},
+    cpu::{disasm, reg_file::Flag, sm83::SM83},
+    error::GBError,
+    ram_search::{Candidate, Filter, RamSearch, Size, Watch},
+};
+
+const CALLS: [u8; 5] = [0xC4, 0xD4, 0xCC, 0xDC, 0xCD];
+const RSTS: [u8; 8] = [0xC7, 0xD7, 0xE7, 0xF7, 0xCF, 0xDF, 0xEF, 0xFF];
+const RETS: [u8; 6] = [0xC9, 0xD9, 0xC0, 0xC8, 0xD0, 0xD8];
+
+const HELP: &str = "\
+break|b [bank:]addr|label  add a breakpoint, the bank only applies to 0x4000-0x7FFF
+delete|d n                 remove breakpoint n
+watch|w [r|w|rw] addr[-end] add a watchpoint, defaults to rw
+unwatch n                  remove watchpoint n
+list|l                     list breakpoints and watchpoints
+step|s [n]                 execute n instructions
+next|n                     step over calls and rsts
+finish|f                   run until the current function returns
+continue|c                 resume emulation
+regs|r                     show the registers and flags
+x addr [len]               dump memory
+cheat [add code [desc]]    list or add GameShark/Game Genie cheats
+cheat on|off|del n         enable, disable or delete cheat n
+search new [8|16]          start a RAM search over WRAM, HRAM and cartridge RAM
+search same|changed|inc|dec keep the hits that compare so against the last search
+search = value             keep the hits holding value, decimal or 0x hex
+search [list]              show the hits
+search pin n [label]       pin hit n to the watch list
+pin addr [8|16] [label]    pin an address to the watch list
+unpin n                    remove pin n
+pins                       show the pinned values
+quit|q                     exit the emulator
+Addresses can also be given as labels from the ROM's .sym file
+An empty line repeats the last command";
+
+/// A PC breakpoint, with `bank: None` it triggers on any ROM bank
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub bank: Option<usize>,
+    pub addr: u16,
+}
+
+/// An inclusive address range checked on every [`Bus::read`]/[`Bus::write`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub start: u16,
+    pub end: u16,
+    pub read: bool,
+    pub write: bool,
+}
+
+impl Watchpoint {
+    pub fn matches(&self, addr: u16, is_write: bool) -> bool {
+        (self.start..=self.end).contains(&addr) && if is_write { self.write } else { self.read }
+    }
+}
+
+/// The first watchpoint access of the current instruction, set by the bus
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchHit {
+    pub index: usize,
+    pub addr: u16,
+    pub value: u8,
+    pub is_write: bool,
+}
+
+/// Why the debugger paused mid-frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    Breakpoint(usize),
+    Watchpoint(WatchHit),
+    Step,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RunMode {
+    Paused,
+    Running,
+    Steps(u32),
+    StepOver { return_addr: u16, sp: u16 },
+    StepOut { sp: u16 },
+}
+
+pub struct Debugger {
+    pub breakpoints: Vec<Breakpoint>,
+    pub search: Option<RamSearch>,
+    /// Watch list, shown live by the frontend
+    pub pins: Vec<Watch>,
+    mode: RunMode,
+    /// The next instruction is the one the user resumed from, its breakpoint already hit
+    resumed: bool,
+    last_command: String,
+}
+
+impl Debugger {
+    pub fn new(paused: bool) -> Self {
+        Self {
+            breakpoints: vec![],
+            search: None,
+            pins: vec![],
+            mode: if paused {
+                RunMode::Paused
+            } else {
+                RunMode::Running
+            },
+            resumed: false,
+            last_command: String::new(),
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.mode == RunMode::Paused
+    }
+
+    /// Emulates until the end of the frame or until a stop condition is hit, does nothing
+    /// while paused
+    pub fn run_frame(&mut self, bus: &mut Bus) -> Result<Option<Stop>, GBError> {
+        if self.is_paused() {
+            return Ok(None);
+        }
+        bus.ppu.frame_flag = false;
+        while !bus.ppu.frame_flag {
+            // Breakpoints stop before their instruction runs, except the one just resumed from.
+            // Idle cycles in HALT or STOP don't count, PC already points past the instruction
+            let executed = bus.registers.exec && !bus.registers.stopped;
+            if executed
+                && !std::mem::take(&mut self.resumed)
+                && let Some(index) = self.breakpoint_at(bus)
+            {
+                return Ok(Some(self.pause(bus, Stop::Breakpoint(index))));
+            }
+            let opcode = bus.memory.dma_read(bus.registers.pc as usize).unwrap_or(0);
+            SM83::step_instruction(bus)?;
+            if let Some(stop) = self.check_stop(bus, executed, opcode) {
+                return Ok(Some(self.pause(bus, stop)));
+            }
+        }
+        Ok(None)
+    }
+
+    fn pause(&mut self, bus: &Bus, stop: Stop) -> Stop {
+        self.mode = RunMode::Paused;
+        match stop {
+            Stop::Breakpoint(index) => println!("Breakpoint {index} hit"),
+            Stop::Watchpoint(hit) => println!(
+                "Watchpoint {} hit: {} {:#06X} = {:#04X}",
+                hit.index,
+                if hit.is_write { "write" } else { "read" },
+                hit.addr,
+                hit.value
+            ),
+            Stop::Step => (),
+        }
+        println!("{}", registers(bus));
+        println!("{}", next_instruction(bus));
+        prompt();
+        stop
+    }
+
+    fn check_stop(&mut self, bus: &mut Bus, executed: bool, opcode: u8) -> Option<Stop> {
+        if let Some(hit) = bus.watch_hit.take() {
+            return Some(Stop::Watchpoint(hit));
+        }
+        let reg = &bus.registers;
+        match self.mode {
+            RunMode::Steps(n) if executed => {
+                if n <= 1 {
+                    return Some(Stop::Step);
+                }
+                self.mode = RunMode::Steps(n - 1);
+            }
+            RunMode::StepOver { return_addr, sp } if reg.pc == return_addr && reg.sp == sp => {
+                return Some(Stop::Step);
+            }
+            RunMode::StepOut { sp } if executed && RETS.contains(&opcode) && reg.sp > sp => {
+                return Some(Stop::Step);
+            }
+            _ => (),
+        }
+        None
+    }
+
+    /// The breakpoint on the instruction at PC
+    fn breakpoint_at(&self, bus: &Bus) -> Option<usize> {
+        let reg = &bus.registers;
+        let bank = match reg.pc {
+            0x0000..0x4000 => Some(0),
+            0x4000..0x8000 => Some(bus.memory.controller.rom_bank()),
+            _ => None,
+        };
+        self.breakpoints.iter().position(|bp| {
+            bp.addr == reg.pc && (bp.bank.is_none() || bank.is_none() || bp.bank == bank)
+        })
+    }
+
+    /// Runs one command line, returns false when the user asked to quit
+    pub fn command(&mut self, bus: &mut Bus, line: &str) -> bool {
+        let was_paused = self.is_paused();
+        let line = if line.trim().is_empty() {
+            self.last_command.clone()
+        } else {
+            self.last_command = line.trim().to_string();
+            self.last_command.clone()
+        };
+        let args: Vec<&str> = line.split_whitespace().collect();
+        let Some(&name) = args.first() else {
+            prompt();
+            return true;
+        };
+        let result = match name {
+            "break" | "b" => self.add_breakpoint(bus, &args[1..]),
+            "delete" | "d" => remove(&mut self.breakpoints, &args[1..]),
+            "watch" | "w" => add_watchpoint(bus, &args[1..]),
+            "unwatch" => remove(&mut bus.watchpoints, &args[1..]),
+            "list" | "l" => {
+                self.list(bus);
+                Ok(())
+            }
+            "step" | "s" => match args.get(1).map(|n| n.parse::<u32>()) {
+                None => Ok(RunMode::Steps(1)),
+                Some(Ok(n)) if n > 0 => Ok(RunMode::Steps(n)),
+                Some(_) => Err("Expected a step count".to_string()),
+            }
+            .map(|mode| self.mode = mode),
+            "next" | "n" => {
+                let opcode = bus.memory.dma_read(bus.registers.pc as usize).unwrap_or(0);
+                let len = if CALLS.contains(&opcode) {
+                    3
+                } else if RSTS.contains(&opcode) {
+                    1
+                } else {
+                    0
+                };
+                self.mode = if len == 0 {
+                    RunMode::Steps(1)
+                } else {
+                    RunMode::StepOver {
+                        return_addr: bus.registers.pc.wrapping_add(len),
+                        sp: bus.registers.sp,
+                    }
+                };
+                Ok(())
+            }
+            "finish" | "f" => {
+                self.mode = RunMode::StepOut {
+                    sp: bus.registers.sp,
+                };
+                Ok(())
+            }
+            "continue" | "c" => {
+                self.mode = RunMode::Running;
+                Ok(())
+            }
+            "regs" | "r" => {
+                println!("{}", registers(bus));
+                println!("{}", next_instruction(bus));
+                Ok(())
+            }
+            "x" => dump(bus, &args[1..]),
+            "cheat" => cheat(bus, &args[1..]),
+            "search" => self.search(bus, &args[1..]),
+            "pin" => self.pin(bus, &args[1..]),
+            "unpin" => remove(&mut self.pins, &args[1..]),
+            "pins" => {
+                for (index, pin) in self.pins.iter().enumerate() {
+                    println!("p{index}: {:04X} {}", pin.addr, pin.show(&bus.memory));
+                }
+                Ok(())
+            }
+            "help" | "h" => {
+                println!("{HELP}");
+                Ok(())
+            }
+            "quit" | "q" => return false,
+            _ => Err(format!("Unknown command {name}, try help")),
+        };
+        if let Err(e) = result {
+            println!("{e}");
+        }
+        if self.is_paused() {
+            prompt();
+        } else if was_paused {
+            self.resumed = true;
+        }
+        true
+    }
+
+    fn add_breakpoint(&mut self, bus: &Bus, args: &[&str]) -> Result<(), String> {
+        let spec = args.first().ok_or("Expected an address")?;
+        let breakpoint = match bus.symbols.find(spec) {
+            Some(symbol) => Breakpoint {
+                bank: (0x4000..0x8000)
+                    .contains(&symbol.addr)
+                    .then_some(symbol.bank),
+                addr: symbol.addr,
+            },
+            None => match spec.split_once(':') {
+                Some((bank, addr)) => Breakpoint {
+                    bank: Some(parse_hex(bank)? as usize),
+                    addr: parse_hex(addr)?,
+                },
+                None => Breakpoint {
+                    bank: None,
+                    addr: parse_hex(spec)?,
+                },
+            },
+        };
+        self.breakpoints.push(breakpoint);
+        println!("Breakpoint {} at {spec}", self.breakpoints.len() - 1);
+        Ok(())
+    }
+
+    fn search(&mut self, bus: &Bus, args: &[&str]) -> Result<(), String> {
+        let filter = match args {
+            ["new", rest @ ..] => {
+                let size = parse_size(rest.first())?;
+                self.search = Some(RamSearch::new(&bus.memory, size));
+                println!("Searching {} addresses", self.candidates()?.len());
+                return Ok(());
+            }
+            ["pin", index, label @ ..] => {
+                let hit = index
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|n| self.candidates().ok()?.get(n).copied())
+                    .ok_or("Expected a valid hit index, see search list")?;
+                let size = self
+                    .search
+                    .as_ref()
+                    .map_or(Size::Byte, |search| search.size);
+                self.pins.push(Watch {
+                    addr: hit.addr,
+                    size,
+                    label: label.join(" "),
+                });
+                println!("Pin {} at {:04X}", self.pins.len() - 1, hit.addr);
+                return Ok(());
+            }
+            [] | ["list"] => {
+                let candidates = self.candidates()?;
+                for (index, hit) in candidates.iter().take(20).enumerate() {
+                    println!("{index}: {:04X} = {}", hit.addr, hit.value);
+                }
+                if candidates.len() > 20 {
+                    println!("... {} more", candidates.len() - 20);
+                }
+                return Ok(());
+            }
+            ["same"] => Filter::Equal,
+            ["changed"] => Filter::Changed,
+            ["inc"] => Filter::Increased,
+            ["dec"] => Filter::Decreased,
+            ["=", value] => Filter::Value(parse_value(value)?),
+            _ => return Err("Expected new, same, changed, inc, dec, = value, list or pin".into()),
+        };
+        let search = self
+            .search
+            .as_mut()
+            .ok_or("No search, start one with search new")?;
+        println!("{} hits", search.filter(&bus.memory, filter));
+        Ok(())
+    }
+
+    fn candidates(&self) -> Result<&[Candidate], String> {
+        self.search
+            .as_ref()
+            .map(RamSearch::candidates)
+            .ok_or_else(|| "No search, start one with search new".to_string())
+    }
+
+    fn pin(&mut self, bus: &Bus, args: &[&str]) -> Result<(), String> {
+        let addr = parse_addr(bus, args.first().ok_or("Expected an address")?)?;
+        let (size, label) = match args.get(1) {
+            Some(&size @ ("8" | "16")) => (parse_size(Some(&size))?, &args[2..]),
+            _ => (Size::Byte, &args[1.min(args.len())..]),
+        };
+        self.pins.push(Watch {
+            addr,
+            size,
+            label: label.join(" "),
+        });
+        println!("Pin {} at {addr:04X}", self.pins.len() - 1);
+        Ok(())
+    }
+
+    fn list(&self, bus: &Bus) {
+        for (index, bp) in self.breakpoints.iter().enumerate() {
+            let label = bus
+                .symbols
+                .label(bp.addr, bp.bank.unwrap_or(bus.memory.controller.rom_bank()))
+                .map_or(String::new(), |label| format!(" {label}"));
+            match bp.bank {
+                Some(bank) => println!("b{index}: {bank:02X}:{:04X}{label}", bp.addr),
+                None => println!("b{index}: {:04X}{label}", bp.addr),
+            }
+        }
+        for (index, watch) in bus.watchpoints.iter().enumerate() {
+            let kind = match (watch.read, watch.write) {
+                (true, true) => "rw",
+                (true, false) => "r",
+                _ => "w",
+            };
+            println!("w{index}: {kind} {:04X}-{:04X}", watch.start, watch.end);
+        }
+    }
+}
+
+/// One line summary of the CPU state, flags are shown as `ZNHC` with cleared ones as `-`
+pub fn registers(bus: &Bus) -> String {
+    let reg = &bus.registers;
+    let flag = |flag: Flag, c: char| if reg.read_flag(flag) { c } else { '-' };
+    format!(
+        "AF={:02X}{:02X} BC={:02X}{:02X} DE={:02X}{:02X} HL={:02X}{:02X} SP={:04X} PC={:04X} [{}{}{}{}] IME={} bank={:02X}",
+        reg.a,
+        reg.f,
+        reg.b,
+        reg.c,
+        reg.d,
+        reg.e,
+        reg.h,
+        reg.l,
+        reg.sp,
+        reg.pc,
+        flag(Flag::Zero, 'Z'),
+        flag(Flag::Subtract, 'N'),
+        flag(Flag::HalfCarry, 'H'),
+        flag(Flag::Carry, 'C'),
+        reg.ime as u8,
+        bus.memory.controller.rom_bank()
+    )
+}
+
+/// The instruction at PC, as `addr: mnemonic` or `addr <label>: mnemonic` with symbols loaded
+pub fn next_instruction(bus: &Bus) -> String {
+    let pc = bus.registers.pc;
+    let bytes: Vec<u8> = (0..3)
+        .map(|i| {
+            bus.memory
+                .dma_read(pc.wrapping_add(i) as usize)
+                .unwrap_or(0xFF)
+        })
+        .collect();
+    let mnemonic = disasm::disassemble(&bytes, pc).mnemonic;
+    let bank = bus.memory.controller.rom_bank();
+    match bus.symbols.label(pc, bank) {
+        Some(label) => format!(
+            "{pc:04X} <{label}>: {}",
+            bus.symbols.annotate(&mnemonic, bank)
+        ),
+        None => format!("{pc:04X}: {mnemonic}"),
+    }
+}
+
+fn prompt() {
+    print!("(redgb) ");
+    let _ = std::io::stdout().flush();
+}
+
+/// A label from the symbol file, or a hex address
+fn parse_addr(bus: &Bus, s: &str) -> Result<u16, String> {
+    match bus.symbols.find(s) {
+        Some(symbol) => Ok(symbol.addr),
+        None => parse_hex(s),
+    }
+}
+
+/// Parses a hex number, with or without a `0x` or `$` prefix
+fn parse_hex(s: &str) -> Result<u16, String> {
+    let digits = s
+        .strip_prefix("0x")
+        .or_else(|| s.strip_prefix('$'))
+        .unwrap_or(s);
+    u16::from_str_radix(digits, 16).map_err(|_| format!("Invalid address {s}"))
+}
+
+/// Parses a decimal number, or a hex one with a `0x` or `$` prefix
+fn parse_value(s: &str) -> Result<u16, String> {
+    if s.starts_with("0x") || s.starts_with('$') {
+        parse_hex(s)
+    } else {
+        s.parse::<u16>().map_err(|_| format!("Invalid value {s}"))
+    }
+}
+
+fn parse_size(arg: Option<&&str>) -> Result<Size, String> {
+    match arg {
+        None | Some(&"8") => Ok(Size::Byte),
+        Some(&"16") => Ok(Size::Word),
+        Some(size) => Err(format!("Invalid size {size}, expected 8 or 16")),
+    }
+}
+
+fn cheat(bus: &mut Bus, args: &[&str]) -> Result<(), String> {
+    let cheats = &mut bus.memory.cheats;
+    let index = || {
+        args.get(1)
+            .and_then(|n| n.parse::<usize>().ok())
+            .filter(|&n| n < cheats.list().len())
+            .ok_or("Expected a valid index, see cheat")
+    };
+    match args {
+        [] => {
+            for (index, cheat) in cheats.list().iter().enumerate() {
+                let state = if cheat.enabled { "on " } else { "off" };
+                println!("c{index}: {state} {} {}", cheat.code, cheat.description);
+            }
+            return Ok(());
+        }
+        ["add", code, description @ ..] => {
+            let cheat = Cheat::parse(code, &description.join(" ")).map_err(|e| e.to_string())?;
+            println!("Cheat {} added", cheats.add(cheat));
+        }
+        ["on" | "off", _] => {
+            let index = index()?;
+            cheats.set_enabled(index, args[0] == "on");
+        }
+        ["del", _] => {
+            let index = index()?;
+            cheats.remove(index);
+        }
+        _ => return Err("Expected add code [desc], on n, off n or del n".to_string()),
+    }
+    let path = cheats::cheat_path(bus.memory.controller.rom_header());
+    bus.memory.cheats.save(&path).map_err(|e| e.to_string())
+}
+
+fn remove<T>(list: &mut Vec<T>, args: &[&str]) -> Result<(), String> {
+    let index = args
+        .first()
+        .and_then(|n| n.parse::<usize>().ok())
+        .filter(|&n| n < list.len())
+        .ok_or("Expected a valid index, see list")?;
+    list.remove(index);
+    Ok(())
+}
+
+fn add_watchpoint(bus: &mut Bus, args: &[&str]) -> Result<(), String> {
+    let (read, write, range) = match args {
+        ["r", range] => (true, false, range),
+        ["w", range] => (false, true, range),
+        ["rw", range] | [range] => (true, true, range),
+        _ => return Err("Expected [r|w|rw] addr[-end]".to_string()),
+    };
+    let (start, end) = match range.split_once('-') {
+        Some((start, end)) => (parse_addr(bus, start)?, parse_addr(bus, end)?),
+        None => (parse_addr(bus, range)?, parse_addr(bus, range)?),
+    };
+    if end < start {
+        return Err("Watchpoint range ends before it starts".to_string());
+    }
+    bus.watchpoints.push(Watchpoint {
+        start,
+        end,
+        read,
+        write,
+    });
+    println!("Watchpoint {} at {range}", bus.watchpoints.len() - 1);
+    Ok(())
+}
+
+fn dump(bus: &Bus, args: &[&str]) -> Result<(), String> {
+    let start = parse_addr(bus, args.first().ok_or("Expected an address")?)?;
+    let len = match args.get(1) {
+        Some(len) => len.parse::<u16>().map_err(|_| "Invalid length")?,
+        None => 16,
+    };
+    for row in (0..len).step_by(16) {
+        let addr = start.wrapping_add(row);
+        let bytes: Vec<String> = (0..(len - row).min(16))
+            .map(
+                |i| match bus.memory.dma_read(addr.wrapping_add(i) as usize) {
+                    Ok(byte) => format!("{byte:02X}"),
+                    Err(_) => "??".to_string(),
+                },
+            )
+            .collect();
+        println!("{addr:04X}: {}", bytes.join(" "));
+    }
+    Ok(())
+}
+
+#[test]
+fn stepping_test() {
+    use crate::gameboy::GameBoy;
+    // call 0x0200, jr -5 | 0x0200: ld [0xC000], a, ret
+    let mut gameboy = GameBoy::test_with_patches(&[
+        (0x100, &[0xCD, 0x00, 0x02, 0x18, 0xFB]),
+        (0x200, &[0xEA, 0x00, 0xC0, 0xC9]),
+    ]);
+    let bus = &mut gameboy.bus;
+    let mut debugger = Debugger::new(true);
+
+    debugger.command(bus, "step");
+    assert_eq!(debugger.run_frame(bus).unwrap(), Some(Stop::Step));
+    assert_eq!(bus.registers.pc, 0x200);
+    debugger.command(bus, "finish");
+    assert_eq!(debugger.run_frame(bus).unwrap(), Some(Stop::Step));
+    assert_eq!(bus.registers.pc, 0x103);
+
+    debugger.command(bus, "b 0:0100");
+    debugger.command(bus, "c");
+    assert_eq!(debugger.run_frame(bus).unwrap(), Some(Stop::Breakpoint(0)));
+    debugger.command(bus, "next");
+    assert_eq!(debugger.run_frame(bus).unwrap(), Some(Stop::Step));
+    assert_eq!(bus.registers.pc, 0x103);
+
+    debugger.command(bus, "d 0");
+    debugger.command(bus, "w w c000");
+    debugger.command(bus, "c");
+    let Some(Stop::Watchpoint(hit)) = debugger.run_frame(bus).unwrap() else {
+        panic!("Expected a watchpoint stop");
+    };
+    assert_eq!((hit.addr, hit.is_write), (0xC000, true));
+
+    bus.symbols = crate::symbols::Symbols::parse("00:0100 Main\n00:0200 Store\n");
+    bus.watchpoints.clear();
+    debugger.command(bus, "b Store");
+    assert_eq!(debugger.breakpoints[0].addr, 0x200);
+    debugger.command(bus, "c");
+    assert_eq!(debugger.run_frame(bus).unwrap(), Some(Stop::Breakpoint(0)));
+    assert_eq!(next_instruction(bus), "0200 <Store>: ld [$C000], a");
+
+    // Breakpoints on a jr -2 loop stop on every lap
+    bus.memory.dma_write(0xC010, 0x18).unwrap();
+    bus.memory.dma_write(0xC011, 0xFE).unwrap();
+    bus.registers.pc = 0xC010;
+    debugger.command(bus, "d 0");
+    debugger.command(bus, "b c010");
+    debugger.command(bus, "c");
+    let cycles = bus.t_cycles;
+    assert_eq!(debugger.run_frame(bus).unwrap(), Some(Stop::Breakpoint(0)));
+    assert_eq!((bus.registers.pc, bus.t_cycles - cycles), (0xC010, 12));
+    debugger.command(bus, "c");
+    assert_eq!(debugger.run_frame(bus).unwrap(), Some(Stop::Breakpoint(0)));
+    assert_eq!((bus.registers.pc, bus.t_cycles - cycles), (0xC010, 24));
+    // A breakpoint on the first instruction hits before it runs
+    let mut debugger = Debugger::new(false);
+    debugger.command(bus, "b c010");
+    assert_eq!(debugger.run_frame(bus).unwrap(), Some(Stop::Breakpoint(0)));
+    assert_eq!((bus.registers.pc, bus.t_cycles - cycles), (0xC010, 24));
+}
@@ -1,36 +1,195 @@
-use std::time::{Duration, Instant};
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver};
+use std::time::Instant;
 
-use log::{debug, info};
+use log::{debug, error, info};
 use ringbuf::HeapRb;
-use ringbuf::traits::Split;
+use ringbuf::traits::{Observer, Producer, Split};
 use sdl3::audio::{AudioFormat, AudioSpec};
 use sdl3::event::Event;
-use sdl3::keyboard::Keycode;
+use sdl3::gamepad::Gamepad;
 use sdl3::pixels::PixelFormat;
 use sdl3::render::ScaleMode;
+use sdl3::sys::joystick::SDL_JoystickID;
 use sdl3::sys::render::SDL_RendererLogicalPresentation;
 
 use crate::apu::buffer;
-use crate::bus::Bus;
-use crate::cpu::reg_file::{Modes, RegFile};
-use crate::cpu::sm83::SM83;
+use crate::bindings::{self, Bindings, Hotkey};
+use crate::cdl::{self, Cdl};
+use crate::cheats::{self, Cheats};
+use crate::debugger::Debugger;
 use crate::error::GBError;
-use crate::mem::map;
-use crate::ppu::ppu::PPU;
+use crate::gameboy::GameBoy;
+use crate::gdb::GdbStub;
+use crate::linked::LinkedPair;
+use crate::pacing::{FRAME_TIME, Pacing, Speed};
+use crate::profiler::Profiler;
+use crate::recorder::{self, Recorder};
+use crate::rewind::Rewind;
 use crate::rom::rom_info::ROMInfo;
+use crate::screenshot;
+use crate::serial::link::TcpLink;
+use crate::serial::printer::Printer;
+use crate::state;
+use crate::symbols::Symbols;
 
-const GB_AUDIO_SPEC: AudioSpec = AudioSpec {
-    freq: Some(1048576),
-    channels: Some(1),
-    format: Some(AudioFormat::f32_sys()),
-};
 const AUDIO_SPEC: AudioSpec = AudioSpec {
     freq: Some(44100),
-    channels: Some(1),
+    channels: Some(2),
     format: Some(AudioFormat::f32_sys()),
 };
 
-pub fn init_emulation(rom: Vec<u8>, header_data: ROMInfo) -> Result<(), GBError> {
+/// Forwards stdin lines to the emulation loop so reading commands never blocks rendering
+fn spawn_stdin_reader() -> Receiver<String> {
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lines().map_while(Result::ok) {
+            if tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    rx
+}
+
+/// Command line options for the frontend
+#[derive(Default)]
+pub struct Options {
+    /// Start paused with the debugger REPL on stdin
+    pub debug: bool,
+    /// Where to write a gameboy-doctor trace
+    pub trace: Option<Box<dyn Write + Send>>,
+    /// Append labels from the `.sym` file to trace lines
+    pub trace_labels: bool,
+    /// DMG or CGB boot ROM to run before the cartridge
+    pub boot_rom: Option<Vec<u8>>,
+    /// Speed while the fast-forward key is held
+    pub turbo: Speed,
+    /// Where screenshots go, [`screenshot::default_dir`] if unset
+    pub screenshot_dir: Option<PathBuf>,
+    /// Screenshot size as a multiple of 160x144
+    pub screenshot_scale: u32,
+    /// What F10 records to, files go to [`recorder::default_dir`]
+    pub record_format: recorder::Format,
+    /// Plug a Game Boy Printer into the serial port, printouts go to the screenshot dir
+    pub printer: bool,
+    /// Link cable to another instance, takes precedence over the printer
+    pub link: Option<TcpLink>,
+    /// GDB remote stub, takes over from the debugger once gdb attaches
+    pub gdb: Option<GdbStub>,
+    /// Labels from the ROM's `.sym` file
+    pub symbols: Symbols,
+    /// Where to keep the Code/Data Log, logging is off without one
+    pub cdl: Option<PathBuf>,
+    /// Where to write the profiler's folded stacks, profiling is off without one
+    pub profile: Option<PathBuf>,
+}
+
+fn emulate_frame(
+    gameboy: &mut GameBoy,
+    debugger: Option<&mut Debugger>,
+    gdb: Option<&mut GdbStub>,
+    rewind: &mut Rewind,
+    recorder: &mut Option<Recorder>,
+) -> Result<(), GBError> {
+    match (gdb.filter(|gdb| gdb.is_attached()), debugger) {
+        (Some(gdb), _) => gdb.run_frame(&mut gameboy.bus)?,
+        (None, Some(debugger)) => {
+            debugger.run_frame(&mut gameboy.bus)?;
+        }
+        (None, None) => gameboy.run_frame()?,
+    }
+    rewind.record(gameboy);
+    if let Some(active) = recorder.as_mut()
+        && let Err(e) = active.capture(gameboy)
+    {
+        error!("{}", e);
+        stop_recording(recorder);
+    }
+    Ok(())
+}
+
+fn save_cdl(gameboy: &GameBoy, path: Option<&PathBuf>) {
+    if let (Some(cdl), Some(path)) = (gameboy.bus.cdl.as_ref(), path) {
+        let (code, data, unused) = cdl.coverage();
+        let mesen_path = cdl::mesen_path(path);
+        match cdl.save(path).and_then(|()| cdl.export_mesen(&mesen_path)) {
+            Ok(()) => info!(
+                "Saved {} and {}: {code} bytes of code, {data} of data, {unused} unused",
+                path.display(),
+                mesen_path.display()
+            ),
+            Err(e) => error!("{}", e),
+        }
+    }
+}
+
+fn save_profile(gameboy: &GameBoy, path: Option<&PathBuf>) {
+    let (Some(profiler), Some(path)) = (gameboy.bus.profiler.as_ref(), path) else {
+        return;
+    };
+    let symbols = &gameboy.bus.symbols;
+    let written = std::fs::File::create(path)
+        .and_then(|file| profiler.write_folded(BufWriter::new(file), symbols));
+    match written {
+        Ok(()) => info!(
+            "Saved {}: {} T-cycles profiled",
+            path.display(),
+            profiler.total_cycles()
+        ),
+        Err(e) => return error!("{}: {e}", path.display()),
+    }
+    for ((bank, pc), cycles) in profiler.hotspots(10) {
+        let label = symbols.label(pc, bank).unwrap_or_default();
+        info!("{bank:02X}:{pc:04X} {label:<24} {cycles}");
+    }
+}
+
+fn stop_recording(recorder: &mut Option<Recorder>) {
+    if let Some(recorder) = recorder.take() {
+        let frames = recorder.frames();
+        match recorder.finish() {
+            Ok(()) => info!("Recording stopped after {frames} frames"),
+            Err(e) => error!("{}", e),
+        }
+    }
+}
+
+fn toggle_recording(recorder: &mut Option<Recorder>, gameboy: &GameBoy, format: recorder::Format) {
+    if recorder.is_some() {
+        stop_recording(recorder);
+    } else {
+        let title = &gameboy.bus.memory.controller.rom_header().title;
+        let path = recorder::new_path(&recorder::default_dir(), title, format);
+        match Recorder::create(&path, format) {
+            Ok(new) => {
+                info!("Recording to {}", path.display());
+                *recorder = Some(new);
+            }
+            Err(e) => error!("{}", e),
+        }
+    }
+}
+
+pub fn init_emulation(rom: Vec<u8>, header_data: ROMInfo, options: Options) -> Result<(), GBError> {
+    let Options {
+        debug,
+        trace,
+        trace_labels,
+        boot_rom,
+        turbo,
+        screenshot_dir,
+        screenshot_scale,
+        record_format,
+        printer,
+        link,
+        mut gdb,
+        symbols,
+        cdl,
+        profile,
+    } = options;
+    let screenshot_dir = screenshot_dir.unwrap_or_else(screenshot::default_dir);
     // Init SDL
     let sdl_bus = sdl3::init().expect("Error: Could not init SDL");
     let video = sdl_bus
@@ -52,14 +211,48 @@ pub fn init_emulation(rom: Vec<u8>, header_data: ROMInfo) -> Result<(), GBError>
     let mut event_pump = sdl_bus
         .event_pump()
         .expect("Error: Could not capture game input");
-    let registers = RegFile::new(Modes::DMG);
-    let memory = map::Memory::init_rom(rom, header_data);
-    let ppu = PPU::default();
+    // Already connected gamepads also come in as added events on the first poll
+    let gamepad_sys = sdl_bus.gamepad().ok();
+    let mut gamepads: Vec<Gamepad> = vec![];
+    let mut bindings = Bindings::load();
+    let cdl_log = cdl.as_ref().map(|path| Cdl::load(path, &rom));
+    let mut gameboy = match boot_rom {
+        Some(boot_rom) => GameBoy::with_boot_rom(rom, header_data, boot_rom)?,
+        None => GameBoy::new(rom, header_data),
+    };
+    gameboy.set_trace(trace);
+    gameboy.bus.trace_labels = trace_labels;
+    gameboy.set_symbols(symbols);
+    gameboy.bus.cdl = cdl_log;
+    if profile.is_some() {
+        gameboy.bus.profiler = Some(Profiler::new());
+    }
+    if let Some(link) = link {
+        gameboy.connect_serial(Some(Box::new(link)));
+    } else if printer {
+        gameboy.connect_serial(Some(Box::new(Printer::new(&screenshot_dir))));
+    }
+    let state_path = state::state_path(gameboy.bus.memory.controller.rom_header(), 0);
+    let cheat_path = cheats::cheat_path(gameboy.bus.memory.controller.rom_header());
+    gameboy.bus.memory.cheats = Cheats::load(&cheat_path);
+    if !gameboy.bus.memory.cheats.list().is_empty() {
+        info!("Loaded cheats from {}", cheat_path.display());
+    }
+    let mut debugger = debug.then(|| Debugger::new(true));
+    let commands = debug.then(spawn_stdin_reader);
+    if let Some(debugger) = debugger.as_mut() {
+        debugger.command(&mut gameboy.bus, "regs");
+    }
+    let mut rewind = Rewind::default();
+    let mut rewinding = false;
+    let mut pacing = Pacing::new(turbo);
+    let mut recorder: Option<Recorder> = None;
     let mut time = Instant::now();
-    let target = Duration::new(0, 16666667);
+    let mut title = String::new();
+    let target = FRAME_TIME;
     let audio_sys = sdl_bus.audio();
-    let audio_buf = HeapRb::<f32>::new(2048);
-    let (prod, cons) = audio_buf.split();
+    let audio_buf = HeapRb::<f32>::new(4096);
+    let (mut prod, cons) = audio_buf.split();
     let callback_struct = buffer::AudioBuffer { buffer: cons };
     let device = if let Ok(audio) = audio_sys {
         let audio_device = audio.open_playback_stream(&AUDIO_SPEC, callback_struct);
@@ -72,51 +265,320 @@ pub fn init_emulation(rom: Vec<u8>, header_data: ROMInfo) -> Result<(), GBError>
     } else {
         None
     };
-    let mut bus = Bus::init(registers, memory, ppu, prod);
-    bus.apu.tick(&bus.memory);
-    bus.memory.io[0x0] = 255;
     loop {
-        SM83::step(&mut bus)?;
-        if time.elapsed() < target {
+        if let (Some(debugger), Some(commands)) = (debugger.as_mut(), commands.as_ref()) {
+            for line in commands.try_iter() {
+                if !debugger.command(&mut gameboy.bus, &line) {
+                    save_cdl(&gameboy, cdl.as_ref());
+                    save_profile(&gameboy, profile.as_ref());
+                    return Ok(());
+                }
+            }
+        }
+        if let Some(gdb) = gdb.as_mut() {
+            gdb.poll(&mut gameboy.bus);
+        }
+        if rewinding {
+            if let Err(e) = rewind.rewind_frame(&mut gameboy) {
+                error!("{}", e);
+            }
+            // Audio is muted while rewinding, the device just runs dry
+            gameboy.drain_audio().for_each(drop);
+        }
+        let held = |debugger: &Option<Debugger>, gdb: &Option<GdbStub>| {
+            debugger.as_ref().is_some_and(Debugger::is_paused)
+                || gdb.as_ref().is_some_and(GdbStub::is_halted)
+        };
+        if !rewinding && !held(&debugger, &gdb) {
+            match pacing.frames_due() {
+                // Keep emulating whole frames until the audio device has enough queued up, unless
+                // the debugger is holding the CPU
+                Some(_) if pacing.plays_audio() => {
+                    while !held(&debugger, &gdb) {
+                        emulate_frame(
+                            &mut gameboy,
+                            debugger.as_mut(),
+                            gdb.as_mut(),
+                            &mut rewind,
+                            &mut recorder,
+                        )?;
+                        prod.push_iter(gameboy.drain_audio());
+                        if prod.occupied_len() > 2048 {
+                            break;
+                        }
+                    }
+                }
+                // Fast-forwarded and advanced frames are silent, their samples would only
+                // overflow the ring buffer
+                due => {
+                    let mut frames = 0;
+                    while !held(&debugger, &gdb)
+                        && match due {
+                            Some(due) => frames < due,
+                            None => time.elapsed() < target,
+                        }
+                    {
+                        emulate_frame(
+                            &mut gameboy,
+                            debugger.as_mut(),
+                            gdb.as_mut(),
+                            &mut rewind,
+                            &mut recorder,
+                        )?;
+                        gameboy.drain_audio().for_each(drop);
+                        frames += 1;
+                    }
+                }
+            }
+        }
+        if pacing.speed() != Speed::Uncapped && time.elapsed() < target {
             std::thread::sleep(target.abs_diff(time.elapsed()));
         }
         let fps = 1.0 / (time.elapsed().as_secs_f32());
         debug!("fps: {}", fps);
         time = Instant::now();
+        let mut quit = false;
         for event in event_pump.poll_iter() {
             match event {
-                Event::Quit { .. }
-                | Event::KeyDown {
-                    keycode: Some(Keycode::Escape),
+                Event::Quit { .. } => quit = true,
+                Event::KeyDown {
+                    keycode: Some(key),
+                    repeat,
                     ..
-                } => {
-                    if let Some(aud_dev) = device {
-                        let _ = aud_dev.pause();
-                    }
-                    info!("Cycle count: {}", &bus.t_cycles);
-                    info!("CPU {:#?}", &bus.registers);
-                    info!("Audio: {:#?}", &bus.memory.io[0x10..=0x26]);
-                    info!("Last Serial message: {}", {
-                        str::from_utf8(&bus.serial_message[..]).unwrap()
-                    });
-                    return Ok(());
+                } => match bindings::hotkey(key) {
+                    Some(Hotkey::Quit) => quit = true,
+                    Some(Hotkey::SaveState) => {
+                        if let Err(e) = state::save_file(&gameboy.bus, &state_path) {
+                            error!("{}", e);
+                        }
+                    }
+                    Some(Hotkey::LoadState) => {
+                        if let Err(e) = state::load_file(&mut gameboy.bus, &state_path) {
+                            error!("{}", e);
+                        }
+                    }
+                    Some(Hotkey::Screenshot) if !repeat => {
+                        match gameboy.screenshot(&screenshot_dir, screenshot_scale) {
+                            Ok(path) => info!("Saved screenshot to {}", path.display()),
+                            Err(e) => error!("{}", e),
+                        }
+                    }
+                    Some(Hotkey::Record) if !repeat => {
+                        toggle_recording(&mut recorder, &gameboy, record_format)
+                    }
+                    Some(Hotkey::Rewind) => rewinding = true,
+                    Some(Hotkey::FastForward) => pacing.set_fast_forward(true),
+                    Some(Hotkey::Pause) if !repeat => pacing.toggle_pause(),
+                    Some(Hotkey::FrameAdvance) => pacing.frame_advance(),
+                    Some(_) => (),
+                    None => {
+                        for (button, pressed) in bindings.key(key, true) {
+                            gameboy.set_button(button, pressed);
+                        }
+                    }
+                },
+                Event::KeyUp {
+                    keycode: Some(key), ..
+                } => match bindings::hotkey(key) {
+                    Some(Hotkey::Rewind) => rewinding = false,
+                    Some(Hotkey::FastForward) => pacing.set_fast_forward(false),
+                    Some(_) => (),
+                    None => {
+                        for (button, pressed) in bindings.key(key, false) {
+                            gameboy.set_button(button, pressed);
+                        }
+                    }
+                },
+                Event::ControllerDeviceAdded { which, .. } => {
+                    if let Some(gamepad_sys) = gamepad_sys.as_ref() {
+                        match gamepad_sys.open(SDL_JoystickID(which)) {
+                            Ok(gamepad) => {
+                                info!("Connected {}", gamepad.name().unwrap_or_default());
+                                gamepads.push(gamepad);
+                            }
+                            Err(e) => error!("Couldn't open gamepad: {e}"),
+                        }
+                    }
+                }
+                Event::ControllerDeviceRemoved { which, .. } => {
+                    gamepads.retain(|gamepad| gamepad.id().is_ok_and(|id| id.0 != which));
                 }
+                Event::ControllerButtonDown { button, .. } => {
+                    for (button, pressed) in bindings.pad_button(button, true) {
+                        gameboy.set_button(button, pressed);
+                    }
+                }
+                Event::ControllerButtonUp { button, .. } => {
+                    for (button, pressed) in bindings.pad_button(button, false) {
+                        gameboy.set_button(button, pressed);
+                    }
+                }
+                Event::ControllerAxisMotion { axis, value, .. } => {
+                    for (button, pressed) in bindings.axis(axis, value) {
+                        gameboy.set_button(button, pressed);
+                    }
+                }
+                _ => (),
+            }
+        }
+        if quit {
+            if let Some(aud_dev) = device {
+                let _ = aud_dev.pause();
+            }
+            stop_recording(&mut recorder);
+            save_cdl(&gameboy, cdl.as_ref());
+            save_profile(&gameboy, profile.as_ref());
+            let bus = &gameboy.bus;
+            info!("Cycle count: {}", &bus.t_cycles);
+            info!("CPU {:#?}", &bus.registers);
+            info!("Audio: {:#?}", &bus.memory.io[0x10..=0x26]);
+            // Printer and link cable traffic goes through here too, it's rarely text
+            info!(
+                "Last Serial message: {}",
+                String::from_utf8_lossy(&bus.serial_message)
+            );
+            return Ok(());
+        }
+        texture
+            .with_lock(None, |buffer: &mut [u8], _: usize| {
+                buffer.copy_from_slice(gameboy.framebuffer());
+            })
+            .unwrap();
+        // Pinned RAM values go in the title bar so they update live
+        let pins = debugger.as_ref().map_or(vec![], |debugger| {
+            debugger
+                .pins
+                .iter()
+                .map(|pin| pin.show(&gameboy.bus.memory))
+                .collect()
+        });
+        let new_title = if pins.is_empty() {
+            "RedGB".to_string()
+        } else {
+            format!("RedGB - {}", pins.join(" "))
+        };
+        if new_title != title {
+            let _ = canvas.window_mut().set_title(&new_title);
+            title = new_title;
+        }
+        canvas.clear();
+        canvas.copy(&texture, None, None).unwrap();
+        canvas.present();
+    }
+}
+
+/// Runs a [`LinkedPair`] side by side in one window. The keyboard plays the left Game Boy and
+/// gamepads the right one, audio comes from the left one
+pub fn init_linked_emulation(mut pair: LinkedPair) -> Result<(), GBError> {
+    let sdl_bus = sdl3::init().expect("Error: Could not init SDL");
+    let video = sdl_bus
+        .video()
+        .expect("Error: Could not init SDL Video subsystem");
+    let window = video
+        .window("RedGB - Linked", 1600, 720)
+        .build()
+        .expect("Error: Could not display window");
+    let mut canvas = window.into_canvas();
+    canvas
+        .set_logical_size(320, 144, SDL_RendererLogicalPresentation::INTEGER_SCALE)
+        .unwrap();
+    let texture_creator = canvas.texture_creator();
+    let mut texture = texture_creator
+        .create_texture_streaming(PixelFormat::RGB24, 320, 144)
+        .expect("Error: Could not create streaming texture");
+    texture.set_scale_mode(ScaleMode::Nearest);
+    let mut event_pump = sdl_bus
+        .event_pump()
+        .expect("Error: Could not capture game input");
+    let gamepad_sys = sdl_bus.gamepad().ok();
+    let mut gamepads: Vec<Gamepad> = vec![];
+    // Each machine has its own inputs, so they hold their buttons apart
+    let mut key_bindings = Bindings::load();
+    let mut pad_bindings = key_bindings.clone();
+    let audio_buf = HeapRb::<f32>::new(4096);
+    let (mut prod, cons) = audio_buf.split();
+    let device = sdl_bus.audio().ok().and_then(|audio| {
+        let device = audio
+            .open_playback_stream(&AUDIO_SPEC, buffer::AudioBuffer { buffer: cons })
+            .ok()?;
+        let _ = device.resume();
+        Some(device)
+    });
+    let mut time = Instant::now();
+    loop {
+        loop {
+            pair.run_frame()?;
+            pair.gameboys[1].drain_audio().for_each(drop);
+            prod.push_iter(pair.gameboys[0].drain_audio());
+            if prod.occupied_len() > 2048 {
+                break;
+            }
+        }
+        if time.elapsed() < FRAME_TIME {
+            std::thread::sleep(FRAME_TIME.abs_diff(time.elapsed()));
+        }
+        time = Instant::now();
+        let [left, right] = &mut pair.gameboys;
+        let mut quit = false;
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. } => quit = true,
+                Event::KeyDown {
+                    keycode: Some(key), ..
+                } if bindings::hotkey(key) == Some(Hotkey::Quit) => quit = true,
                 Event::KeyDown {
                     keycode: Some(key), ..
                 } => {
-                    bus.joypad.update(key, true);
+                    for (button, pressed) in key_bindings.key(key, true) {
+                        left.set_button(button, pressed);
+                    }
                 }
                 Event::KeyUp {
                     keycode: Some(key), ..
                 } => {
-                    bus.joypad.update(key, false);
+                    for (button, pressed) in key_bindings.key(key, false) {
+                        left.set_button(button, pressed);
+                    }
+                }
+                Event::ControllerDeviceAdded { which, .. } => {
+                    if let Some(gamepad) = gamepad_sys
+                        .as_ref()
+                        .and_then(|sys| sys.open(SDL_JoystickID(which)).ok())
+                    {
+                        gamepads.push(gamepad);
+                    }
+                }
+                Event::ControllerDeviceRemoved { which, .. } => {
+                    gamepads.retain(|gamepad| gamepad.id().is_ok_and(|id| id.0 != which));
+                }
+                Event::ControllerButtonDown { button, .. } => {
+                    for (button, pressed) in pad_bindings.pad_button(button, true) {
+                        right.set_button(button, pressed);
+                    }
+                }
+                Event::ControllerButtonUp { button, .. } => {
+                    for (button, pressed) in pad_bindings.pad_button(button, false) {
+                        right.set_button(button, pressed);
+                    }
+                }
+                Event::ControllerAxisMotion { axis, value, .. } => {
+                    for (button, pressed) in pad_bindings.axis(axis, value) {
+                        right.set_button(button, pressed);
+                    }
                 }
                 _ => (),
             }
         }
+        if quit {
+            if let Some(aud_dev) = device {
+                let _ = aud_dev.pause();
+            }
+            return Ok(());
+        }
+        let frame = pair.framebuffer();
         texture
             .with_lock(None, |buffer: &mut [u8], _: usize| {
-                buffer.copy_from_slice(bus.ppu.framebuffer.as_slice());
+                buffer.copy_from_slice(&frame);
             })
             .unwrap();
         canvas.clear();
@@ -22,4 +22,22 @@ pub enum GBError {
     SaveError,
     #[error("Failed to load :<")]
     LoadError,
+    #[error("Save state is invalid or corrupted")]
+    InvalidState,
+    #[error("Save state version {0} is newer than this build supports")]
+    UnsupportedStateVersion(u16),
+    #[error("Save state was made with a different ROM")]
+    StateRomMismatch,
+    #[error("Boot ROM is {0} bytes, expected 256 (DMG) or 2304 (CGB)")]
+    InvalidBootRom(usize),
+    #[error(
+        "The CGB boot ROM can only start CGB cartridges, DMG compatibility mode isn't emulated"
+    )]
+    UnsupportedBootRom,
+    #[error("Failed to write image: {0}")]
+    ImageError(String),
+    #[error("Recording failed: {0}")]
+    RecordError(String),
+    #[error("Invalid cheat code {0}")]
+    InvalidCheat(String),
 }
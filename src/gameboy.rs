@@ -0,0 +1,199 @@
+use std::path::{Path, PathBuf};
+
+use crate::{
+    bus::Bus,
+    cpu::{
+        input::Button,
+        reg_file::{Modes, RegFile},
+        sm83::SM83,
+    },
+    error::GBError,
+    mem::map::Memory,
+    ppu::ppu::PPU,
+    rom::rom_info::{CGBMode, ROMInfo},
+    screenshot,
+    serial::SerialDevice,
+    state,
+    symbols::Symbols,
+};
+
+const BUTTONS: [Button; 8] = [
+    Button::A,
+    Button::B,
+    Button::Select,
+    Button::Start,
+    Button::Up,
+    Button::Down,
+    Button::Left,
+    Button::Right,
+];
+
+/// Headless emulation core, owns the whole machine state and knows nothing about
+/// windows, audio devices or input backends
+pub struct GameBoy {
+    pub bus: Bus,
+}
+
+impl GameBoy {
+    /// Starts right where the boot ROM would have handed over to the cartridge
+    pub fn new(rom: Vec<u8>, header_data: ROMInfo) -> Self {
+        let mut memory = Memory::init_rom(rom, header_data);
+        let mode = if memory.cgb { Modes::CGB } else { Modes::DMG };
+        memory.init_post_boot_io(mode);
+        let mut bus = Bus::init(RegFile::new(mode), memory, PPU::default());
+        bus.apu.tick(&bus.memory);
+        Self { bus }
+    }
+
+    /// Starts from power on, running `boot_rom` until it unmaps itself through 0xFF50
+    pub fn with_boot_rom(
+        rom: Vec<u8>,
+        header_data: ROMInfo,
+        boot_rom: Vec<u8>,
+    ) -> Result<Self, GBError> {
+        if boot_rom.len() != 0x100 && boot_rom.len() != 0x900 {
+            return Err(GBError::InvalidBootRom(boot_rom.len()));
+        }
+        // On a DMG cartridge it would switch itself to compatibility mode and set up palettes
+        if boot_rom.len() == 0x900 && !matches!(header_data.cgb, CGBMode::Color { .. }) {
+            return Err(GBError::UnsupportedBootRom);
+        }
+        let mut memory = Memory::init_rom(rom, header_data);
+        memory.map_boot_rom(boot_rom);
+        memory.set_vram_bank(0);
+        memory.set_wram_bank(1);
+        let mut bus = Bus::init(RegFile::power_on(), memory, PPU::default());
+        bus.apu.tick(&bus.memory);
+        Ok(Self { bus })
+    }
+
+    /// Emulates until the PPU has finished drawing a frame
+    pub fn run_frame(&mut self) -> Result<(), GBError> {
+        SM83::step(&mut self.bus)
+    }
+
+    /// The last drawn frame as 160x144 RGB24 pixels
+    pub fn framebuffer(&self) -> &[u8] {
+        self.bus.ppu.framebuffer.as_slice()
+    }
+
+    /// Saves the last drawn frame as a timestamped PNG in `dir`, `scale` times the native size
+    pub fn screenshot(&self, dir: &Path, scale: u32) -> Result<PathBuf, GBError> {
+        let title = &self.bus.memory.controller.rom_header().title;
+        screenshot::save(self.framebuffer(), dir, title, scale)
+    }
+
+    /// Takes the 44.1 KHz samples produced since the last call, interleaved as left/right pairs
+    pub fn drain_audio(&mut self) -> std::vec::Drain<'_, f32> {
+        self.bus.apu.samples.drain(..)
+    }
+
+    /// Sets the held buttons, every button not in `pressed` is released
+    pub fn set_buttons(&mut self, pressed: &[Button]) {
+        for button in BUTTONS {
+            self.bus.joypad.update(button, pressed.contains(&button));
+        }
+    }
+
+    pub fn set_button(&mut self, button: Button, is_down: bool) {
+        self.bus.joypad.update(button, is_down);
+    }
+
+    /// Plugs `device` into the serial port, `None` unplugs whatever was connected
+    pub fn connect_serial(&mut self, device: Option<Box<dyn SerialDevice>>) {
+        self.bus.serial.device = device;
+    }
+
+    /// Starts writing a gameboy-doctor trace line per instruction to `out`, `None` stops it.
+    /// LY reads as 0x90 while tracing
+    pub fn set_trace(&mut self, out: Option<Box<dyn std::io::Write + Send>>) {
+        self.bus.trace = out;
+    }
+
+    /// Labels from an RGBDS `.sym` file for the debugger and traces
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.bus.symbols = symbols;
+    }
+
+    /// Snapshot of the whole machine in the versioned format described in [`state`]
+    pub fn save_state(&self) -> Vec<u8> {
+        state::save(&self.bus)
+    }
+
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), GBError> {
+        state::load(&mut self.bus, data)
+    }
+}
+
+#[cfg(test)]
+impl GameBoy {
+    /// Header of the 32 KiB ROM-only cartridge unit tests run on
+    pub(crate) fn test_header() -> ROMInfo {
+        ROMInfo {
+            cartridge_type: 0,
+            rom_banks: 2,
+            mem_banks: 0,
+            ..ROMInfo::default()
+        }
+    }
+
+    /// [`Self::test_header`] for a CGB-only cartridge
+    pub(crate) fn test_cgb_header() -> ROMInfo {
+        ROMInfo {
+            cgb: crate::rom::rom_info::CGBMode::Color { exclusive: true },
+            ..Self::test_header()
+        }
+    }
+
+    /// An empty 32 KiB ROM with every `(addr, bytes)` patch copied in
+    pub(crate) fn test_rom(patches: &[(usize, &[u8])]) -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        for &(addr, bytes) in patches {
+            rom[addr..addr + bytes.len()].copy_from_slice(bytes);
+        }
+        rom
+    }
+
+    /// A Game Boy running [`Self::test_rom`], execution starts at 0x0100
+    pub(crate) fn test_with_patches(patches: &[(usize, &[u8])]) -> Self {
+        Self::new(Self::test_rom(patches), Self::test_header())
+    }
+
+    /// A Game Boy with just `code` at the entry point
+    pub(crate) fn test_with_code(code: &[u8]) -> Self {
+        Self::test_with_patches(&[(0x100, code)])
+    }
+}
+
+#[test]
+fn boot_rom_test() {
+    // jr -2
+    let rom = GameBoy::test_rom(&[(0x100, &[0x18, 0xFE])]);
+    let mut boot_rom = vec![0; 0x100];
+    // ld a, 1, jp 0x00FC | 0x00FC: ldh [0x50], a, (falls through to 0x0100)
+    boot_rom[..5].copy_from_slice(&[0x3E, 0x01, 0xC3, 0xFC, 0x00]);
+    boot_rom[0xFC..0xFE].copy_from_slice(&[0xE0, 0x50]);
+    let mut gameboy = GameBoy::with_boot_rom(rom, GameBoy::test_header(), boot_rom).unwrap();
+    assert_eq!(gameboy.bus.memory.dma_read(0x0000).unwrap(), 0x3E);
+    gameboy.run_frame().unwrap();
+    assert!(!gameboy.bus.memory.boot_rom_mapped);
+    assert_eq!(gameboy.bus.memory.dma_read(0x0000).unwrap(), 0x00);
+    assert_eq!(gameboy.bus.registers.pc & 0xFFFE, 0x100);
+    assert!(GameBoy::with_boot_rom(vec![0; 0x8000], GameBoy::test_header(), vec![0; 10]).is_err());
+    let cgb_boot_rom = vec![0; 0x900];
+    assert!(matches!(
+        GameBoy::with_boot_rom(
+            vec![0; 0x8000],
+            GameBoy::test_header(),
+            cgb_boot_rom.clone()
+        ),
+        Err(GBError::UnsupportedBootRom)
+    ));
+    assert!(
+        GameBoy::with_boot_rom(vec![0; 0x8000], GameBoy::test_cgb_header(), cgb_boot_rom)
+            .unwrap()
+            .bus
+            .memory
+            .cgb
+    );
+}
@@ -0,0 +1,511 @@
+//! GDB remote serial protocol stub
+//!
+//! Lets gdb and gdb based frontends attach with `target remote localhost:<port>`. The stub never
+//! blocks, the frontend calls [`GdbStub::poll`] once per loop and drives the CPU through
+//! [`GdbStub::run_frame`], which stops on software breakpoints and single steps.
+//!
+//! There's no SM83 target in gdb, the registers are described in `target.xml` as six 16-bit
+//! little endian pairs: AF, BC, DE, HL, SP and PC.
+
+use std::{
+    io::{self, ErrorKind, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+};
+
+use log::{info, warn};
+
+use crate::{bus::Bus, cpu::sm83::SM83, error::GBError};
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.redgb.sm83.cpu">
+    <reg name="af" bitsize="16" type="uint16"/>
+    <reg name="bc" bitsize="16" type="uint16"/>
+    <reg name="de" bitsize="16" type="uint16"/>
+    <reg name="hl" bitsize="16" type="uint16"/>
+    <reg name="sp" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+  </feature>
+</target>"#;
+/// Largest packet we accept, advertised in qSupported
+const PACKET_SIZE: usize = 0x1000;
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RunState {
+    Halted,
+    Running,
+    Step,
+}
+
+pub struct GdbStub {
+    listener: TcpListener,
+    client: Option<TcpStream>,
+    /// Received bytes that don't make up a whole packet yet
+    pending: Vec<u8>,
+    no_ack: bool,
+    pub breakpoints: Vec<u16>,
+    state: RunState,
+    /// The next instruction is the one gdb continued or stepped from, its breakpoint already hit
+    resumed: bool,
+}
+
+impl GdbStub {
+    /// Listens for gdb on `addr`, emulation runs freely until it attaches
+    pub fn bind(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        info!("Waiting for gdb on {}", listener.local_addr()?);
+        Ok(Self {
+            listener,
+            client: None,
+            pending: vec![],
+            no_ack: false,
+            breakpoints: vec![],
+            state: RunState::Running,
+            resumed: false,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn is_attached(&self) -> bool {
+        self.client.is_some()
+    }
+
+    /// gdb is holding the CPU
+    pub fn is_halted(&self) -> bool {
+        self.state == RunState::Halted
+    }
+
+    /// Accepts a new connection and handles every packet that has arrived
+    pub fn poll(&mut self, bus: &mut Bus) {
+        if self.client.is_none() {
+            self.accept();
+        }
+        let Some(client) = self.client.as_mut() else {
+            return;
+        };
+        let mut buf = [0; 1024];
+        loop {
+            match client.read(&mut buf) {
+                Ok(0) => {
+                    self.disconnect(io::Error::from(ErrorKind::UnexpectedEof));
+                    return;
+                }
+                Ok(len) => self.pending.extend_from_slice(&buf[..len]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    self.disconnect(e);
+                    return;
+                }
+            }
+        }
+        while let Some(packet) = self.next_packet() {
+            if let Some(reply) = self.handle(bus, &packet) {
+                self.send(&reply);
+            }
+        }
+    }
+
+    fn accept(&mut self) {
+        match self.listener.accept() {
+            Ok((stream, peer)) => {
+                if let Err(e) = stream.set_nonblocking(true) {
+                    warn!("gdb connection failed: {e}");
+                    return;
+                }
+                info!("gdb attached from {peer}");
+                let _ = stream.set_nodelay(true);
+                self.client = Some(stream);
+                self.pending.clear();
+                self.no_ack = false;
+                // gdb expects the target to be stopped when it attaches
+                self.state = RunState::Halted;
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => (),
+            Err(e) => warn!("gdb connection failed: {e}"),
+        }
+    }
+
+    /// Drops the client and lets the game run on
+    fn disconnect(&mut self, e: io::Error) {
+        if e.kind() != ErrorKind::UnexpectedEof {
+            warn!("gdb disconnected: {e}");
+        } else {
+            info!("gdb detached");
+        }
+        self.client = None;
+        self.breakpoints.clear();
+        self.state = RunState::Running;
+    }
+
+    /// Pops the next packet's payload, answering its checksum. A Ctrl-C comes out as `\x03`
+    fn next_packet(&mut self) -> Option<Vec<u8>> {
+        loop {
+            let start = self.pending.iter().position(|&b| b == b'$' || b == 0x03)?;
+            if self.pending[start] == 0x03 {
+                self.pending.drain(..=start);
+                return Some(vec![0x03]);
+            }
+            let end = self.pending[start..].iter().position(|&b| b == b'#')? + start;
+            if self.pending.len() < end + 3 {
+                return None;
+            }
+            let payload = self.pending[start + 1..end].to_vec();
+            let checksum = std::str::from_utf8(&self.pending[end + 1..end + 3])
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+            self.pending.drain(..end + 3);
+            let valid = checksum == Some(payload.iter().fold(0, |sum: u8, &b| sum.wrapping_add(b)));
+            if !self.no_ack {
+                self.write(if valid { b"+" } else { b"-" });
+            }
+            if valid {
+                return Some(payload);
+            }
+        }
+    }
+
+    fn send(&mut self, payload: &str) {
+        let checksum = payload.bytes().fold(0, |sum: u8, b| sum.wrapping_add(b));
+        self.write(format!("${payload}#{checksum:02x}").as_bytes());
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        // Replies are small enough to always fit in the socket buffer
+        if let Some(client) = self.client.as_mut()
+            && let Err(e) = client.write_all(bytes)
+        {
+            self.disconnect(e);
+        }
+    }
+
+    /// Answers one packet, `None` for packets without a reply (continue and step answer once the
+    /// CPU stops)
+    fn handle(&mut self, bus: &mut Bus, packet: &[u8]) -> Option<String> {
+        let packet = String::from_utf8_lossy(packet);
+        let (command, args) = packet.split_at_checked(1).unwrap_or_default();
+        let reply = match command {
+            "\x03" => {
+                if self.state == RunState::Halted {
+                    return None;
+                }
+                self.state = RunState::Halted;
+                format!("S{SIGINT:02x}")
+            }
+            "?" => format!("S{SIGTRAP:02x}"),
+            "g" => registers(bus).iter().map(|reg| hex_u16(*reg)).collect(),
+            "G" => match parse_registers(args) {
+                Some(regs) => {
+                    set_registers(bus, regs);
+                    "OK".to_string()
+                }
+                None => "E01".to_string(),
+            },
+            "p" => match usize::from_str_radix(args, 16) {
+                Ok(index) if index < 6 => hex_u16(registers(bus)[index]),
+                _ => "E01".to_string(),
+            },
+            "P" => {
+                let parsed = args.split_once('=').and_then(|(index, value)| {
+                    let index = usize::from_str_radix(index, 16).ok().filter(|&i| i < 6)?;
+                    Some((index, parse_u16_le(value)?))
+                });
+                match parsed {
+                    Some((index, value)) => {
+                        let mut regs = registers(bus);
+                        regs[index] = value;
+                        set_registers(bus, regs);
+                        "OK".to_string()
+                    }
+                    None => "E01".to_string(),
+                }
+            }
+            "m" => match parse_range(args) {
+                Some((addr, len)) => (0..len)
+                    .map(|i| bus.memory.dma_read(addr.wrapping_add(i) as usize))
+                    .collect::<Result<Vec<u8>, _>>()
+                    .map_or("E14".to_string(), |bytes| hex(&bytes)),
+                None => "E01".to_string(),
+            },
+            "M" => {
+                let parsed = args.split_once(':').and_then(|(range, data)| {
+                    let (addr, len) = parse_range(range)?;
+                    let bytes = parse_hex_bytes(data)?;
+                    (bytes.len() == len as usize).then_some((addr, bytes))
+                });
+                match parsed {
+                    // Writes to ROM would switch banks or poke MBC registers instead
+                    Some((addr, bytes))
+                        if (0..bytes.len() as u16).any(|i| addr.wrapping_add(i) < 0x8000) =>
+                    {
+                        "E14".to_string()
+                    }
+                    Some((addr, bytes)) => {
+                        let written = bytes.iter().enumerate().try_for_each(|(i, &byte)| {
+                            bus.memory
+                                .dma_write(addr.wrapping_add(i as u16) as usize, byte)
+                        });
+                        if written.is_ok() { "OK" } else { "E14" }.to_string()
+                    }
+                    None => "E01".to_string(),
+                }
+            }
+            "c" | "s" => {
+                if let Ok(addr) = u16::from_str_radix(args, 16) {
+                    bus.registers.pc = addr;
+                }
+                self.state = if command == "c" {
+                    RunState::Running
+                } else {
+                    RunState::Step
+                };
+                self.resumed = true;
+                return None;
+            }
+            "Z" | "z" => {
+                // Hardware breakpoints behave the same, there's no limit on them anyway
+                let parsed = args
+                    .strip_prefix("0,")
+                    .or_else(|| args.strip_prefix("1,"))
+                    .and_then(|rest| rest.split(',').next())
+                    .and_then(|addr| u16::from_str_radix(addr, 16).ok());
+                match parsed {
+                    Some(addr) if command == "Z" => {
+                        if !self.breakpoints.contains(&addr) {
+                            self.breakpoints.push(addr);
+                        }
+                        "OK".to_string()
+                    }
+                    Some(addr) => {
+                        self.breakpoints.retain(|&bp| bp != addr);
+                        "OK".to_string()
+                    }
+                    None => String::new(),
+                }
+            }
+            "q" | "Q" => self.query(&packet),
+            "H" | "T" => "OK".to_string(),
+            "D" => {
+                self.send("OK");
+                self.disconnect(io::Error::from(ErrorKind::UnexpectedEof));
+                return None;
+            }
+            "k" => {
+                self.disconnect(io::Error::from(ErrorKind::UnexpectedEof));
+                return None;
+            }
+            _ => String::new(),
+        };
+        Some(reply)
+    }
+
+    fn query(&mut self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            return format!(
+                "PacketSize={PACKET_SIZE:x};qXfer:features:read+;QStartNoAckMode+;swbreak+"
+            );
+        }
+        if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            let Some((offset, len)) = range.split_once(',').and_then(|(offset, len)| {
+                Some((
+                    usize::from_str_radix(offset, 16).ok()?,
+                    usize::from_str_radix(len, 16).ok()?,
+                ))
+            }) else {
+                return "E01".to_string();
+            };
+            let chunk = TARGET_XML.get(offset..).unwrap_or("");
+            return if chunk.len() > len {
+                format!("m{}", &chunk[..len])
+            } else {
+                format!("l{chunk}")
+            };
+        }
+        match packet {
+            "QStartNoAckMode" => {
+                // The OK itself is still acknowledged
+                self.send("OK");
+                self.no_ack = true;
+                String::new()
+            }
+            "qAttached" => "1".to_string(),
+            "qC" => "QC1".to_string(),
+            "qfThreadInfo" => "m1".to_string(),
+            "qsThreadInfo" => "l".to_string(),
+            _ => String::new(),
+        }
+    }
+
+    /// Emulates until the end of the frame, a breakpoint, or the end of a single step. Does
+    /// nothing while halted
+    pub fn run_frame(&mut self, bus: &mut Bus) -> Result<(), GBError> {
+        if self.is_halted() {
+            return Ok(());
+        }
+        bus.ppu.frame_flag = false;
+        while !bus.ppu.frame_flag {
+            // Breakpoints stop before their instruction runs, except the one just resumed from.
+            // Idle cycles in HALT or STOP don't count, PC already points past the instruction
+            let executes = bus.registers.exec && !bus.registers.stopped;
+            if executes
+                && !std::mem::take(&mut self.resumed)
+                && self.breakpoints.contains(&bus.registers.pc)
+            {
+                self.stop();
+                return Ok(());
+            }
+            SM83::step_instruction(bus)?;
+            if self.state == RunState::Step {
+                self.stop();
+                return Ok(());
+            }
+        }
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        self.state = RunState::Halted;
+        if self.client.is_some() {
+            self.send(&format!("S{SIGTRAP:02x}"));
+        }
+    }
+}
+
+fn registers(bus: &Bus) -> [u16; 6] {
+    let reg = &bus.registers;
+    let pair = |high: u8, low: u8| (high as u16) << 8 | low as u16;
+    [
+        pair(reg.a, reg.f),
+        pair(reg.b, reg.c),
+        pair(reg.d, reg.e),
+        pair(reg.h, reg.l),
+        reg.sp,
+        reg.pc,
+    ]
+}
+
+fn set_registers(bus: &mut Bus, [af, bc, de, hl, sp, pc]: [u16; 6]) {
+    let reg = &mut bus.registers;
+    [reg.a, reg.f] = af.to_be_bytes();
+    // The low nibble of F doesn't exist
+    reg.f &= 0xF0;
+    [reg.b, reg.c] = bc.to_be_bytes();
+    [reg.d, reg.e] = de.to_be_bytes();
+    [reg.h, reg.l] = hl.to_be_bytes();
+    reg.sp = sp;
+    reg.pc = pc;
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Registers go over the wire in target byte order, little endian
+fn hex_u16(value: u16) -> String {
+    hex(&value.to_le_bytes())
+}
+
+fn parse_hex_bytes(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn parse_u16_le(s: &str) -> Option<u16> {
+    match parse_hex_bytes(s)?[..] {
+        [low, high] => Some(u16::from_le_bytes([low, high])),
+        _ => None,
+    }
+}
+
+fn parse_registers(s: &str) -> Option<[u16; 6]> {
+    let bytes = parse_hex_bytes(s)?;
+    let mut regs = [0; 6];
+    for (reg, pair) in regs.iter_mut().zip(bytes.chunks_exact(2)) {
+        *reg = u16::from_le_bytes([pair[0], pair[1]]);
+    }
+    (bytes.len() >= 12).then_some(regs)
+}
+
+/// `addr,len` in hex
+fn parse_range(s: &str) -> Option<(u16, u16)> {
+    let (addr, len) = s.split_once(',')?;
+    let len = u16::from_str_radix(len, 16).ok()?;
+    Some((
+        u16::from_str_radix(addr, 16).ok()?,
+        len.min(PACKET_SIZE as u16 / 2),
+    ))
+}
+
+#[test]
+fn gdb_test() {
+    use crate::gameboy::GameBoy;
+    // ld a, 0x42 | ld [0xC000], a | jr -7
+    let mut gameboy = GameBoy::test_with_code(&[0x3E, 0x42, 0xEA, 0x00, 0xC0, 0x18, 0xF9]);
+    let bus = &mut gameboy.bus;
+    let mut stub = GdbStub::bind("127.0.0.1:0").unwrap();
+    let mut gdb = TcpStream::connect(stub.local_addr().unwrap()).unwrap();
+    gdb.set_read_timeout(Some(std::time::Duration::from_secs(5)))
+        .unwrap();
+
+    let mut request = |stub: &mut GdbStub, bus: &mut Bus, payload: &str| -> String {
+        let checksum = payload.bytes().fold(0, |sum: u8, b| sum.wrapping_add(b));
+        gdb.write_all(format!("${payload}#{checksum:02x}").as_bytes())
+            .unwrap();
+        let mut reply = vec![];
+        while !(reply.len() > 3 && reply[reply.len() - 3] == b'#') {
+            stub.poll(bus);
+            stub.run_frame(bus).unwrap();
+            let mut buf = [0; 256];
+            if let Ok(len) = gdb.read(&mut buf) {
+                reply.extend_from_slice(&buf[..len]);
+            }
+        }
+        let reply = String::from_utf8(reply).unwrap();
+        let start = reply.find('$').unwrap();
+        reply[start + 1..reply.len() - 3].to_string()
+    };
+    assert_eq!(request(&mut stub, bus, "?"), "S05");
+    assert!(stub.is_halted());
+    assert_eq!(&request(&mut stub, bus, "g")[20..], "0001");
+    assert_eq!(request(&mut stub, bus, "s"), "S05");
+    assert_eq!(bus.registers.pc, 0x102);
+    assert_eq!(request(&mut stub, bus, "p0")[2..], *"42");
+    assert_eq!(request(&mut stub, bus, "Z0,105,1"), "OK");
+    assert_eq!(request(&mut stub, bus, "c"), "S05");
+    assert_eq!(bus.registers.pc, 0x105);
+    assert_eq!(request(&mut stub, bus, "mc000,2"), "4200");
+    assert_eq!(request(&mut stub, bus, "Mc001,1:99"), "OK");
+    assert_eq!(bus.memory.dma_read(0xC001).unwrap(), 0x99);
+    assert_eq!(request(&mut stub, bus, "P5=0001"), "OK");
+    assert_eq!(bus.registers.pc, 0x100);
+    assert_eq!(request(&mut stub, bus, "M0150,1:00"), "E14");
+    assert_eq!(request(&mut stub, bus, "M7fff,2:0000"), "E14");
+    assert_eq!(request(&mut stub, bus, "z0,105,1"), "OK");
+    // 0xC010: jr -2 | 0xC012: nop | halt
+    assert_eq!(request(&mut stub, bus, "Mc010,4:18fe0076"), "OK");
+    assert_eq!(request(&mut stub, bus, "Z0,c010,1"), "OK");
+    assert_eq!(request(&mut stub, bus, "P5=10c0"), "OK");
+    assert_eq!(request(&mut stub, bus, "c"), "S05");
+    assert_eq!(bus.registers.pc, 0xC010);
+    // Continuing runs the jr once and stops on it again
+    let cycles = bus.t_cycles;
+    assert_eq!(request(&mut stub, bus, "c"), "S05");
+    assert_eq!((bus.registers.pc, bus.t_cycles - cycles), (0xC010, 12));
+    assert_eq!(request(&mut stub, bus, "Z0,c013,1"), "OK");
+    assert_eq!(request(&mut stub, bus, "P5=12c0"), "OK");
+    assert_eq!(request(&mut stub, bus, "c"), "S05");
+    assert!(bus.registers.exec);
+    assert_eq!(bus.registers.pc, 0xC013);
+    assert!(request(&mut stub, bus, "qXfer:features:read:target.xml:0,1000").starts_with("l<?xml"));
+}
@@ -1,9 +1,29 @@
 pub mod apu;
+#[cfg(feature = "sdl")]
+pub mod bindings;
 pub mod bus;
+pub mod cdl;
+pub mod cheats;
 pub mod cpu;
+pub mod debugger;
+#[cfg(feature = "sdl")]
 pub mod emulator;
 pub mod error;
+pub mod gameboy;
+pub mod gdb;
+pub mod linked;
 pub mod mbc;
 pub mod mem;
+pub mod pacing;
 pub mod ppu;
+pub mod profiler;
+pub mod ram_search;
+pub mod recorder;
+pub mod rewind;
 pub mod rom;
+pub mod screenshot;
+pub mod serial;
+pub mod state;
+pub mod symbols;
+
+pub use gameboy::GameBoy;
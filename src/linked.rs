@@ -0,0 +1,120 @@
+//! Two Game Boys in one process, connected by a virtual link cable
+//!
+//! Both machines are stepped an instruction at a time, always advancing whichever one is behind,
+//! so they never drift apart by more than one instruction. Everything is deterministic, the same
+//! inputs give the same run every time, which makes it usable for multiplayer tests.
+
+use std::sync::{Arc, Mutex};
+
+use crate::{
+    cpu::{alu, sm83::SM83},
+    error::GBError,
+    gameboy::GameBoy,
+    serial::SerialDevice,
+};
+
+const SB: usize = 0x01;
+const SC: usize = 0x02;
+
+#[derive(Default, Clone, Copy)]
+struct Port {
+    /// SB while this side waits on the external clock
+    waiting: Option<u8>,
+    /// A byte the other side clocked in, delivered on this side's next poll
+    incoming: Option<u8>,
+}
+
+/// One end of the virtual cable, plugged into each Game Boy's serial port
+struct WireEnd {
+    wire: Arc<Mutex<[Port; 2]>>,
+    side: usize,
+}
+
+impl SerialDevice for WireEnd {
+    fn transfer(&mut self, byte: u8) -> u8 {
+        let mut wire = self.wire.lock().unwrap();
+        let other = &mut wire[1 - self.side];
+        match other.waiting.take() {
+            Some(reply) => {
+                other.incoming = Some(byte);
+                reply
+            }
+            None => 0xFF,
+        }
+    }
+
+    fn poll(&mut self, _waiting: Option<u8>) -> Option<u8> {
+        self.wire.lock().unwrap()[self.side].incoming.take()
+    }
+}
+
+pub struct LinkedPair {
+    /// Left and right Game Boy, the left one paces [`LinkedPair::run_frame`]
+    pub gameboys: [GameBoy; 2],
+    wire: Arc<Mutex<[Port; 2]>>,
+}
+
+impl LinkedPair {
+    /// Links `left` and `right`, replacing whatever was plugged into their serial ports
+    pub fn new(left: GameBoy, right: GameBoy) -> Self {
+        let wire = Arc::new(Mutex::new([Port::default(); 2]));
+        let mut gameboys = [left, right];
+        for (side, gameboy) in gameboys.iter_mut().enumerate() {
+            gameboy.connect_serial(Some(Box::new(WireEnd {
+                wire: wire.clone(),
+                side,
+            })));
+        }
+        Self { gameboys, wire }
+    }
+
+    /// Emulates until the left Game Boy has finished a frame, the right one keeps pace
+    pub fn run_frame(&mut self) -> Result<(), GBError> {
+        for gameboy in self.gameboys.iter_mut() {
+            gameboy.bus.ppu.frame_flag = false;
+        }
+        while !self.gameboys[0].bus.ppu.frame_flag {
+            let side = if self.gameboys[0].bus.t_cycles <= self.gameboys[1].bus.t_cycles {
+                0
+            } else {
+                1
+            };
+            let bus = &mut self.gameboys[side].bus;
+            SM83::step_instruction(bus)?;
+            let sc = bus.memory.io[SC];
+            let waiting = alu::read_bits(sc, 7, 1) == 1 && alu::read_bits(sc, 0, 1) == 0;
+            self.wire.lock().unwrap()[side].waiting = waiting.then_some(bus.memory.io[SB]);
+        }
+        Ok(())
+    }
+
+    /// Both last drawn frames next to each other, 320x144 RGB24
+    pub fn framebuffer(&self) -> Vec<u8> {
+        let [left, right] = &self.gameboys;
+        left.framebuffer()
+            .chunks(160 * 3)
+            .zip(right.framebuffer().chunks(160 * 3))
+            .flat_map(|(left, right)| left.iter().chain(right))
+            .copied()
+            .collect()
+    }
+}
+
+#[test]
+fn linked_pair_test() {
+    // ld a, n | ldh [SB], a | ld a, sc | ldh [SC], a | jr -2
+    let transfer = |sb: u8, sc: u8| [0x3E, sb, 0xE0, 0x01, 0x3E, sc, 0xE0, 0x02, 0x18, 0xFE];
+    let master = GameBoy::test_with_code(&transfer(0x42, 0x81));
+    let slave = GameBoy::test_with_code(&transfer(0x99, 0x80));
+    let mut pair = LinkedPair::new(master, slave);
+    pair.run_frame().unwrap();
+    let [master, slave] = &pair.gameboys;
+    assert_eq!(master.bus.memory.io[SB], 0x99);
+    assert_eq!(slave.bus.memory.io[SB], 0x42);
+    for gameboy in &pair.gameboys {
+        assert_eq!(gameboy.bus.memory.io[SC] & 0x80, 0);
+        assert_eq!(gameboy.bus.memory.io[0x0F] & 0x08, 0x08);
+    }
+    assert!(master.bus.t_cycles.abs_diff(slave.bus.t_cycles) <= 24);
+    assert_eq!(pair.framebuffer().len(), 320 * 144 * 3);
+}
@@ -1,14 +1,143 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
+use redgb::GameBoy;
+use redgb::cpu::disasm;
 use redgb::emulator;
+use redgb::gdb::GdbStub;
+use redgb::linked::LinkedPair;
+use redgb::pacing::Speed;
+use redgb::recorder;
 use redgb::rom::{rom_info, rom_parser};
+use redgb::serial::link::TcpLink;
+use redgb::symbols::{self, Symbols};
+#[cfg(not(debug_assertions))]
 use rfd::FileDialog;
-use std::path::PathBuf;
-use std::{env, fs, io, io::Write};
+use std::{
+    env, fs,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// Options the `--pair` frontend has no use for
+const PAIR_UNSUPPORTED: [&str; 14] = [
+    "--debug",
+    "--trace",
+    "--trace-labels",
+    "--boot",
+    "--turbo",
+    "--screenshots",
+    "--screenshot-scale",
+    "--record-png",
+    "--printer",
+    "--link-host",
+    "--link-connect",
+    "--gdb",
+    "--cdl",
+    "--profile",
+];
 
 fn main() {
     env_logger::init();
-    let args: Vec<String> = env::args().collect();
-    let mut rom_path: String = String::new();
+    let mut args: Vec<String> = env::args().collect();
+    if args.get(1).is_some_and(|arg| arg == "disasm") {
+        disassemble_bank(&args[2..]);
+        return;
+    }
+    // --pair runs without any of the other options, refuse them instead of ignoring them
+    if args.iter().any(|arg| arg == "--pair")
+        && let Some(flag) = args
+            .iter()
+            .find(|arg| PAIR_UNSUPPORTED.contains(&arg.as_str()))
+    {
+        return eprintln!("{flag} can't be combined with --pair");
+    }
+    // --debug starts paused with the debugger REPL on stdin
+    let debug = take_flag(&mut args, "--debug");
+    // --trace <file> writes a gameboy-doctor log
+    let trace = match take_flag_value(&mut args, "--trace") {
+        Ok(path) => path.map(|path| {
+            let file = fs::File::create(path).expect("Failed to create trace file");
+            Box::new(BufWriter::new(file)) as Box<dyn Write + Send>
+        }),
+        Err(e) => return eprintln!("{e}"),
+    };
+    // --trace-labels appends the label of PC to trace lines
+    let trace_labels = take_flag(&mut args, "--trace-labels");
+    // --boot <file> runs a DMG or CGB boot ROM first
+    let boot_rom = match take_flag_value(&mut args, "--boot") {
+        Ok(path) => path.map(|path| fs::read(path).expect("Failed to read boot ROM")),
+        Err(e) => return eprintln!("{e}"),
+    };
+    // --turbo <N> fast-forwards at N times speed, 0 for uncapped
+    let turbo = match parse_flag_value(&mut args, "--turbo") {
+        Ok(None | Some(0)) => Speed::Uncapped,
+        Ok(Some(n)) => Speed::Times(n),
+        Err(e) => return eprintln!("{e}"),
+    };
+    // --screenshots <dir> and --screenshot-scale <N> for F12 screenshots
+    let screenshot_dir = match take_flag_value(&mut args, "--screenshots") {
+        Ok(dir) => dir.map(PathBuf::from),
+        Err(e) => return eprintln!("{e}"),
+    };
+    let screenshot_scale = match parse_flag_value::<u32>(&mut args, "--screenshot-scale") {
+        Ok(scale) => scale.unwrap_or(1).max(1),
+        Err(e) => return eprintln!("{e}"),
+    };
+    // --record-png records PNG frames and a WAV instead of an AVI
+    let record_format = if take_flag(&mut args, "--record-png") {
+        recorder::Format::PngWav
+    } else {
+        recorder::Format::Avi
+    };
+    // --printer connects a Game Boy Printer
+    let printer = take_flag(&mut args, "--printer");
+    // --link-host <addr> waits for another instance to connect, --link-connect <addr> connects to it
+    let link = match (
+        take_flag_value(&mut args, "--link-host"),
+        take_flag_value(&mut args, "--link-connect"),
+    ) {
+        (Err(e), _) | (_, Err(e)) => return eprintln!("{e}"),
+        (Ok(Some(_)), Ok(Some(_))) => {
+            return eprintln!("--link-host and --link-connect are exclusive");
+        }
+        (Ok(Some(addr)), Ok(None)) => {
+            println!("Waiting for the other Game Boy on {addr}");
+            Some(TcpLink::host(&addr))
+        }
+        (Ok(None), Ok(Some(addr))) => Some(TcpLink::connect(&addr)),
+        (Ok(None), Ok(None)) => None,
+    };
+    let link = match link.transpose() {
+        Ok(link) => link,
+        Err(e) => return eprintln!("Link cable failed: {e}"),
+    };
+    // --gdb <port> serves the GDB remote protocol on localhost
+    let gdb = match parse_flag_value::<u16>(&mut args, "--gdb") {
+        Ok(port) => match port
+            .map(|port| GdbStub::bind(("127.0.0.1", port)))
+            .transpose()
+        {
+            Ok(gdb) => gdb,
+            Err(e) => return eprintln!("Couldn't start the gdb stub: {e}"),
+        },
+        Err(e) => return eprintln!("{e}"),
+    };
+    // --cdl <file> logs which ROM bytes are code and data, resuming from the file if it exists
+    let cdl = match take_flag_value(&mut args, "--cdl") {
+        Ok(path) => path.map(PathBuf::from),
+        Err(e) => return eprintln!("{e}"),
+    };
+    // --profile <file> writes the cycles spent per call stack as folded stacks for flamegraphs
+    let profile = match take_flag_value(&mut args, "--profile") {
+        Ok(path) => path.map(PathBuf::from),
+        Err(e) => return eprintln!("{e}"),
+    };
+    // --pair <rom> runs a second Game Boy next to the first one, linked by a virtual cable
+    let pair_rom = match take_flag_value(&mut args, "--pair") {
+        Ok(path) => path.map(|path| fs::read(path).expect("Failed to read file")),
+        Err(e) => return eprintln!("{e}"),
+    };
+    let rom_path: String;
 
     if args.len() < 2 {
         #[cfg(not(debug_assertions))]
@@ -33,10 +162,129 @@ fn main() {
         rom_path = args[1].clone();
     }
     println!("Reading input rom: {rom_path}");
-    let rom = fs::read(rom_path).expect("Failed to read file");
+    let rom = fs::read(&rom_path).expect("Failed to read file");
+    let symbols = load_symbols(Path::new(&rom_path));
     let info: rom_info::ROMInfo = rom_parser::parse_rom_header(&rom);
-    match emulator::init_emulation(rom, info) {
+    if let Some(pair_rom) = pair_rom {
+        let mut pair_info = rom_parser::parse_rom_header(&pair_rom);
+        // Both sides may run the same game, the right one gets its own save, states and cheats
+        pair_info.save_suffix = "-2".to_string();
+        let pair = LinkedPair::new(GameBoy::new(rom, info), GameBoy::new(pair_rom, pair_info));
+        if let Err(e) = emulator::init_linked_emulation(pair) {
+            eprintln!("{}", e);
+        }
+        return;
+    }
+    let options = emulator::Options {
+        debug,
+        trace,
+        trace_labels,
+        boot_rom,
+        turbo,
+        screenshot_dir,
+        screenshot_scale,
+        record_format,
+        printer,
+        link,
+        gdb,
+        symbols,
+        cdl,
+        profile,
+    };
+    match emulator::init_emulation(rom, info, options) {
         Ok(()) => (),
         Err(s) => eprintln!("{}", s),
     }
 }
+
+/// Removes every `flag` from `args`, true if there was one
+fn take_flag(args: &mut Vec<String>, flag: &str) -> bool {
+    let len = args.len();
+    args.retain(|arg| arg != flag);
+    args.len() != len
+}
+
+/// Removes `flag` and the value after it from `args`
+fn take_flag_value(args: &mut Vec<String>, flag: &str) -> Result<Option<String>, String> {
+    let Some(index) = args.iter().position(|arg| arg == flag) else {
+        return Ok(None);
+    };
+    if index + 1 >= args.len() {
+        return Err(format!("{flag} expects a value"));
+    }
+    Ok(args.drain(index..=index + 1).nth(1))
+}
+
+/// [`take_flag_value`] for numbers
+fn parse_flag_value<T: FromStr>(args: &mut Vec<String>, flag: &str) -> Result<Option<T>, String> {
+    take_flag_value(args, flag)?
+        .map(|value| {
+            value
+                .parse()
+                .map_err(|_| format!("{flag} expects a number, got {value}"))
+        })
+        .transpose()
+}
+
+/// The RGBDS symbols next to the ROM, if there are any
+fn load_symbols(rom_path: &Path) -> Symbols {
+    let path = symbols::sym_path(rom_path);
+    if !path.exists() {
+        return Symbols::default();
+    }
+    match Symbols::load(&path) {
+        Ok(symbols) => {
+            eprintln!("Loaded {} symbols from {}", symbols.len(), path.display());
+            symbols
+        }
+        Err(e) => {
+            eprintln!("{}: {e}", path.display());
+            Symbols::default()
+        }
+    }
+}
+
+/// `redgb disasm rom.gb [--bank N]`, prints a linear sweep of one ROM bank, with labels from the
+/// ROM's `.sym` file if there is one
+fn disassemble_bank(args: &[String]) {
+    let mut rom_path = None;
+    let mut bank = 0;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--bank" => match args.next().and_then(|n| n.parse::<usize>().ok()) {
+                Some(n) => bank = n,
+                None => return eprintln!("--bank expects a bank number"),
+            },
+            path => rom_path = Some(path),
+        }
+    }
+    let Some(rom_path) = rom_path else {
+        return eprintln!("Usage: redgb disasm rom.gb [--bank N]");
+    };
+    let rom = fs::read(rom_path).expect("Failed to read file");
+    let symbols = load_symbols(Path::new(rom_path));
+    let Some(data) = rom.chunks(0x4000).nth(bank) else {
+        return eprintln!("Bank {bank} is out of range");
+    };
+    let base: u16 = if bank == 0 { 0x0000 } else { 0x4000 };
+    let mut offset = 0;
+    while offset < data.len() {
+        let addr = base + offset as u16;
+        let ins = disasm::disassemble(&data[offset..], addr);
+        if let Some(label) = symbols.exact(addr, bank) {
+            println!("{label}:");
+        }
+        let len = (ins.len as usize).max(1);
+        let bytes: Vec<String> = data[offset..offset + len]
+            .iter()
+            .map(|byte| format!("{byte:02X}"))
+            .collect();
+        println!(
+            "{bank:02X}:{addr:04X}  {:<9} {}",
+            bytes.join(" "),
+            symbols.annotate(&ins.mnemonic, bank)
+        );
+        offset += len;
+    }
+}
@@ -1,14 +1,24 @@
 pub mod mbc1;
 pub mod mbc2;
 pub mod mbc3;
+pub mod mbc5;
 
 use std::fmt::Debug;
 use std::{any::Any, path::PathBuf};
 
-use crate::{error::GBError, rom::rom_info::ROMInfo};
+use crate::{error::GBError, rom::rom_info::ROMInfo, state::SaveState};
 
-pub trait Mbc: Debug + Any {
+/// Save states cover the banking registers and RAM (+ RTC), the ROM is never part of them
+pub trait Mbc: Debug + Any + SaveState {
     fn as_any(&mut self) -> &mut dyn Any;
+    fn rom_header(&self) -> &ROMInfo;
+    /// The ROM bank currently mapped at 0x4000-0x7FFF
+    fn rom_bank(&self) -> usize;
+    /// The ROM bank currently mapped at 0x0000-0x3FFF, only MBC1 can map anything but bank 0
+    fn zero_bank(&self) -> usize {
+        0
+    }
+    /// `len` bytes from `addr`, cut short at the end of the bank it's in
     fn read_range(&self, addr: usize, len: usize) -> Option<&[u8]>;
     fn save(&self) -> Result<(), GBError>;
     fn load(&mut self) -> Result<(), GBError>;
@@ -22,7 +32,11 @@ pub trait MbcFactory {
 }
 
 pub fn save_path(rom_header: &ROMInfo) -> PathBuf {
-    let filename = format!("{}.sav", rom_header.title.trim_end_matches('\0'));
+    let filename = format!(
+        "{}{}.sav",
+        rom_header.title.trim_end_matches('\0'),
+        rom_header.save_suffix
+    );
 
     let dir = dirs::data_dir()
         .unwrap_or_else(|| PathBuf::from("."))
@@ -1,6 +1,12 @@
 use std::any::Any;
 
-use crate::{cpu::alu, error::GBError, mbc, rom::rom_info::ROMInfo};
+use crate::{
+    cpu::alu,
+    error::GBError,
+    mbc,
+    rom::rom_info::ROMInfo,
+    state::{SaveState, StateReader, StateWriter},
+};
 
 use super::{Mbc, MbcFactory};
 #[derive(Debug)]
@@ -21,6 +27,15 @@ impl Mbc for MBC1 {
     fn as_any(&mut self) -> &mut dyn Any {
         self
     }
+    fn rom_header(&self) -> &ROMInfo {
+        &self.rom_header
+    }
+    fn rom_bank(&self) -> usize {
+        self.rom_index_b
+    }
+    fn zero_bank(&self) -> usize {
+        self.rom_index_a
+    }
     fn load(&mut self) -> Result<(), GBError> {
         let data = match std::fs::read(mbc::save_path(&self.rom_header)) {
             Ok(data) => data,
@@ -48,16 +63,16 @@ impl Mbc for MBC1 {
     }
     fn read_range(&self, addr: usize, len: usize) -> Option<&[u8]> {
         match addr {
-            0x0..0x4000 => self.rom_banks[self.rom_index_a].get(addr..=((addr + len).min(0x4000))),
+            0x0..0x4000 => self.rom_banks[self.rom_index_a].get(addr..(addr + len).min(0x4000)),
             0x4000..0x8000 => {
                 let start = addr - 0x4000;
-                let end = (start + len).min(0x8000);
-                self.rom_banks[self.rom_index_b].get(start..=end)
+                let end = (start + len).min(0x4000);
+                self.rom_banks[self.rom_index_b].get(start..end)
             }
             0xA000..0xC000 => {
                 let start = addr - 0xA000;
-                let end = (start + len).min(0xC000);
-                self.eram[self.eram_index].get(start..=end)
+                let end = (start + len).min(0x2000);
+                self.eram[self.eram_index].get(start..end)
             }
             _ => None,
         }
@@ -127,7 +142,7 @@ impl MbcFactory for MBC1 {
             eram_index: 0,
         };
         if mbc1.rom_header.cartridge_type == 3 {
-            mbc1.load();
+            let _ = mbc1.load();
         }
         mbc1
     }
@@ -146,3 +161,22 @@ impl MBC1 {
         }
     }
 }
+
+impl SaveState for MBC1 {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.banks(&self.eram);
+        w.bool(self.eram_enable);
+        w.u8(self.bank_1);
+        w.u8(self.bank_2);
+        w.u8(self.mode);
+    }
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), GBError> {
+        r.banks(&mut self.eram)?;
+        self.eram_enable = r.bool()?;
+        self.bank_1 = r.u8()? & 0x1F;
+        self.bank_2 = r.u8()? & 0x3;
+        self.mode = r.u8()? & 1;
+        self.update_index();
+        Ok(())
+    }
+}
@@ -1,6 +1,12 @@
 use std::any::Any;
 
-use crate::{cpu::alu, error::GBError, mbc, rom::rom_info::ROMInfo};
+use crate::{
+    cpu::alu,
+    error::GBError,
+    mbc,
+    rom::rom_info::ROMInfo,
+    state::{SaveState, StateReader, StateWriter},
+};
 
 use super::{Mbc, MbcFactory};
 #[derive(Debug)]
@@ -17,6 +23,12 @@ impl Mbc for MBC2 {
     fn as_any(&mut self) -> &mut dyn Any {
         self
     }
+    fn rom_header(&self) -> &ROMInfo {
+        &self.rom_header
+    }
+    fn rom_bank(&self) -> usize {
+        self.rom_index_b
+    }
     fn load(&mut self) -> Result<(), GBError> {
         let data = match std::fs::read(mbc::save_path(&self.rom_header)) {
             Ok(data) => data,
@@ -44,16 +56,16 @@ impl Mbc for MBC2 {
     }
     fn read_range(&self, addr: usize, len: usize) -> Option<&[u8]> {
         match addr {
-            0x0..0x4000 => self.rom_banks[0].get(addr..=((addr + len).min(0x4000))),
+            0x0..0x4000 => self.rom_banks[0].get(addr..(addr + len).min(0x4000)),
             0x4000..0x8000 => {
                 let start = addr - 0x4000;
-                let end = (start + len).min(0x8000);
-                self.rom_banks[self.rom_index_b].get(start..=end)
+                let end = (start + len).min(0x4000);
+                self.rom_banks[self.rom_index_b].get(start..end)
             }
             0xA000..0xC000 => {
                 let start = addr - 0xA000;
-                let end = (start + len).min(0xC000);
-                self.eram[0].get(start..=end)
+                let end = (start + len).min(0x2000);
+                self.eram[0].get(start..end)
             }
             _ => None,
         }
@@ -107,7 +119,7 @@ impl MbcFactory for MBC2 {
             rom_index_b: 1,
         };
         if mbc2.rom_header.cartridge_type == 6 {
-            mbc2.load();
+            let _ = mbc2.load();
         }
         mbc2
     }
@@ -118,3 +130,18 @@ impl MBC2 {
         self.rom_index_b = (self.bank_1 % self.rom_bank_count as u8) as usize;
     }
 }
+
+impl SaveState for MBC2 {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.banks(&self.eram);
+        w.bool(self.eram_enable);
+        w.u8(self.bank_1);
+    }
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), GBError> {
+        r.banks(&mut self.eram)?;
+        self.eram_enable = r.bool()?;
+        self.bank_1 = r.u8()? & 0xF;
+        self.update_index();
+        Ok(())
+    }
+}
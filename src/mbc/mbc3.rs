@@ -1,15 +1,14 @@
 use std::{
     any::Any,
-    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+    time::{SystemTime, UNIX_EPOCH},
 };
 
-use sdl3::sys::breakpoint;
-
 use crate::{
     cpu::alu,
     error::GBError,
     mbc::{self, Mbc, MbcFactory},
     rom::rom_info::ROMInfo,
+    state::{SaveState, StateReader, StateWriter},
 };
 
 /// Real time clock
@@ -198,6 +197,34 @@ impl RTC {
     }
 }
 
+impl SaveState for RTC {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.u16(self.sub_seconds);
+        w.u8(self.seconds);
+        w.u8(self.minutes);
+        w.u8(self.hours);
+        w.u16(self.days);
+        w.bool(self.carry);
+        w.bytes(&self.latched_registers);
+        w.u64(self.last_cycle);
+        w.bool(self.is_halted);
+        w.bool(self.is_latched);
+    }
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), GBError> {
+        self.sub_seconds = r.u16()?;
+        self.seconds = r.u8()?;
+        self.minutes = r.u8()?;
+        self.hours = r.u8()?;
+        self.days = r.u16()?;
+        self.carry = r.bool()?;
+        r.fill(&mut self.latched_registers)?;
+        self.last_cycle = r.u64()?;
+        self.is_halted = r.bool()?;
+        self.is_latched = r.bool()?;
+        Ok(())
+    }
+}
+
 impl Default for RTC {
     fn default() -> Self {
         Self::new()
@@ -220,6 +247,12 @@ impl Mbc for MBC3 {
     fn as_any(&mut self) -> &mut dyn Any {
         self
     }
+    fn rom_header(&self) -> &ROMInfo {
+        &self.rom_header
+    }
+    fn rom_bank(&self) -> usize {
+        self.selected_bank as usize
+    }
     fn load(&mut self) -> Result<(), GBError> {
         let data = match std::fs::read(mbc::save_path(&self.rom_header)) {
             Ok(data) => data,
@@ -256,17 +289,17 @@ impl Mbc for MBC3 {
     }
     fn read_range(&self, addr: usize, len: usize) -> Option<&[u8]> {
         match addr {
-            0x0..0x4000 => self.rom[0].get(addr..=(addr + len).min(0x4000)),
+            0x0..0x4000 => self.rom[0].get(addr..(addr + len).min(0x4000)),
             0x4000..0x8000 => {
                 let start = addr - 0x4000;
-                let end = (start + len).min(0x8000);
-                self.rom[self.selected_bank as usize].get(start..=end)
+                let end = (start + len).min(0x4000);
+                self.rom[self.selected_bank as usize].get(start..end)
             }
             0xA000..0xC000 => {
                 if self.eram_rtc_select <= 0x3 {
                     let start = addr - 0xA000;
-                    let end = (start + len).min(0xC000);
-                    self.eram[self.eram_rtc_select as usize].get(start..=end)
+                    let end = (start + len).min(0x2000);
+                    self.eram[self.eram_rtc_select as usize].get(start..end)
                 } else {
                     None
                 }
@@ -351,3 +384,25 @@ impl MbcFactory for MBC3 {
         mbc3
     }
 }
+
+impl SaveState for MBC3 {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.banks(&self.eram);
+        w.u8(self.selected_bank);
+        w.bool(self.rtc_latch);
+        w.u8(self.eram_rtc_select);
+        w.bool(self.eram_enable);
+        self.rtc.save_state(w);
+    }
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), GBError> {
+        r.banks(&mut self.eram)?;
+        self.selected_bank = r.u8()?;
+        if self.selected_bank as usize >= self.rom.len() {
+            return Err(GBError::InvalidState);
+        }
+        self.rtc_latch = r.bool()?;
+        self.eram_rtc_select = r.u8()?;
+        self.eram_enable = r.bool()?;
+        self.rtc.load_state(r)
+    }
+}
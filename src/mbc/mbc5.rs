@@ -0,0 +1,230 @@
+use std::any::Any;
+
+use crate::{
+    cpu::alu,
+    error::GBError,
+    mbc::{self, Mbc, MbcFactory},
+    rom::rom_info::ROMInfo,
+    state::{SaveState, StateReader, StateWriter},
+};
+
+const BATTERY_TYPES: [u8; 2] = [0x1B, 0x1E];
+const RUMBLE_TYPES: [u8; 3] = [0x1C, 0x1D, 0x1E];
+
+#[derive(Debug)]
+pub struct MBC5 {
+    rom: Vec<Vec<u8>>,
+    eram: Vec<Vec<u8>>,
+    rom_header: ROMInfo,
+    /// 9-bit ROM bank number, unlike MBC1/3 bank 0 can be mapped to 0x4000
+    rom_bank: u16,
+    eram_bank: u8,
+    eram_enable: bool,
+    has_rumble: bool,
+    pub rumble: bool,
+}
+
+impl Mbc for MBC5 {
+    fn as_any(&mut self) -> &mut dyn Any {
+        self
+    }
+    fn rom_header(&self) -> &ROMInfo {
+        &self.rom_header
+    }
+    fn rom_bank(&self) -> usize {
+        self.rom_index()
+    }
+    fn load(&mut self) -> Result<(), GBError> {
+        let data = match std::fs::read(mbc::save_path(&self.rom_header)) {
+            Ok(data) => data,
+            Err(_) => return Err(GBError::LoadError),
+        };
+        for (index, bank) in data.chunks(0x2000).enumerate() {
+            if let Some(b) = self.eram.get_mut(index)
+                && bank.len() == 0x2000
+            {
+                *b = bank.to_vec()
+            }
+        }
+        Ok(())
+    }
+    fn save(&self) -> Result<(), GBError> {
+        let mut save_data = vec![];
+        for bank in &self.eram {
+            for byte in bank {
+                save_data.push(*byte);
+            }
+        }
+        log::info!("Saving Game");
+        match std::fs::write(mbc::save_path(&self.rom_header), save_data.as_slice()) {
+            Ok(_) => Ok(()),
+            Err(_) => Err(GBError::SaveError),
+        }
+    }
+    fn read_range(&self, addr: usize, len: usize) -> Option<&[u8]> {
+        match addr {
+            0x0..0x4000 => self.rom[0].get(addr..(addr + len).min(0x4000)),
+            0x4000..0x8000 => {
+                let start = addr - 0x4000;
+                let end = (start + len).min(0x4000);
+                self.rom[self.rom_index()].get(start..end)
+            }
+            0xA000..0xC000 => {
+                let start = addr - 0xA000;
+                let end = (start + len).min(0x2000);
+                self.eram.get(self.eram_bank as usize)?.get(start..end)
+            }
+            _ => None,
+        }
+    }
+    fn read(&self, addr: usize) -> u8 {
+        match addr {
+            0x0..0x4000 => self.rom[0].get(addr).copied().unwrap_or(0xFF),
+            0x4000..0x8000 => self.rom[self.rom_index()]
+                .get(addr - 0x4000)
+                .copied()
+                .unwrap_or(0xFF),
+            0xA000..0xC000 if self.eram_enable => self
+                .eram
+                .get(self.eram_bank as usize)
+                .and_then(|bank| bank.get(addr - 0xA000))
+                .copied()
+                .unwrap_or(0xFF),
+            _ => 0xFF,
+        }
+    }
+    fn write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0..0x2000 => {
+                let prev = self.eram_enable;
+                self.eram_enable = alu::read_bits(value, 0, 4) == 0xA;
+                if prev
+                    && !self.eram_enable
+                    && BATTERY_TYPES.contains(&self.rom_header.cartridge_type)
+                {
+                    // Losing the save file is bad, stopping the game over it is worse
+                    if let Err(e) = self.save() {
+                        log::error!("{e}");
+                    }
+                }
+            }
+            0x2000..0x3000 => self.rom_bank = (self.rom_bank & 0x100) | value as u16,
+            0x3000..0x4000 => {
+                self.rom_bank = (self.rom_bank & 0xFF) | ((alu::read_bits(value, 0, 1) as u16) << 8)
+            }
+            0x4000..0x6000 => {
+                if self.has_rumble {
+                    // Bit 3 drives the rumble motor instead of selecting RAM banks
+                    let rumble = alu::read_bits(value, 3, 1) == 1;
+                    if rumble != self.rumble {
+                        log::debug!("Rumble {}", if rumble { "on" } else { "off" });
+                    }
+                    self.rumble = rumble;
+                    self.eram_bank = alu::read_bits(value, 0, 3);
+                } else {
+                    self.eram_bank = alu::read_bits(value, 0, 4);
+                }
+            }
+            0xA000..0xC000 if self.eram_enable => {
+                if let Some(byte) = self
+                    .eram
+                    .get_mut(self.eram_bank as usize)
+                    .and_then(|bank| bank.get_mut(addr as usize - 0xA000))
+                {
+                    *byte = value;
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+impl MbcFactory for MBC5 {
+    fn new(rom: Vec<u8>, header: ROMInfo) -> Self
+    where
+        Self: Sized,
+    {
+        let mut rom_banks: Vec<Vec<u8>> = Vec::new();
+        for bank in rom.chunks(0x4000) {
+            rom_banks.push(bank.to_vec());
+        }
+        let mut mbc5 = Self {
+            rom: rom_banks,
+            eram: vec![vec![0; 0x2000]; header.mem_banks as usize],
+            has_rumble: RUMBLE_TYPES.contains(&header.cartridge_type),
+            rom_header: header,
+            rom_bank: 1,
+            eram_bank: 0,
+            eram_enable: false,
+            rumble: false,
+        };
+        if BATTERY_TYPES.contains(&mbc5.rom_header.cartridge_type) {
+            let _ = mbc5.load();
+        }
+        mbc5
+    }
+}
+
+impl MBC5 {
+    fn rom_index(&self) -> usize {
+        self.rom_bank as usize % self.rom.len()
+    }
+}
+
+impl SaveState for MBC5 {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.banks(&self.eram);
+        w.u16(self.rom_bank);
+        w.u8(self.eram_bank);
+        w.bool(self.eram_enable);
+        w.bool(self.rumble);
+    }
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), GBError> {
+        r.banks(&mut self.eram)?;
+        self.rom_bank = r.u16()? & 0x1FF;
+        self.eram_bank = r.u8()? & 0xF;
+        self.eram_enable = r.bool()?;
+        self.rumble = r.bool()?;
+        Ok(())
+    }
+}
+
+#[test]
+fn banking_test() {
+    let header = ROMInfo {
+        cartridge_type: 0x1A,
+        rom_banks: 512,
+        mem_banks: 16,
+        ..ROMInfo::default()
+    };
+    let mut rom = vec![0; 0x4000 * 512];
+    for (index, bank) in rom.chunks_mut(0x4000).enumerate() {
+        bank[0] = index as u8;
+        bank[1] = (index >> 8) as u8;
+    }
+    let mut mbc5 = MBC5::new(rom, header);
+    let bank = |mbc5: &MBC5| mbc5.read(0x4000) as u16 | (mbc5.read(0x4001) as u16) << 8;
+    assert_eq!(bank(&mbc5), 1);
+    mbc5.write(0x2000, 0);
+    assert_eq!(bank(&mbc5), 0);
+    mbc5.write(0x2000, 0x23);
+    mbc5.write(0x3000, 1);
+    assert_eq!(bank(&mbc5), 0x123);
+
+    mbc5.write(0xA000, 0x42);
+    assert_eq!(mbc5.read(0xA000), 0xFF);
+    mbc5.write(0x0000, 0x0A);
+    mbc5.write(0x4000, 0xF);
+    mbc5.write(0xA000, 0x42);
+    mbc5.write(0x4000, 0);
+    assert_ne!(mbc5.read(0xA000), 0x42);
+    mbc5.write(0x4000, 0xF);
+    assert_eq!(mbc5.read(0xA000), 0x42);
+
+    // Ranges hold exactly `len` bytes and stop at the end of the bank
+    assert_eq!(mbc5.read_range(0x4000, 0xA0).unwrap()[..2], [0x23, 0x01]);
+    assert_eq!(mbc5.read_range(0x4000, 0xA0).unwrap().len(), 0xA0);
+    assert_eq!(mbc5.read_range(0x7F00, 0x200).unwrap().len(), 0x100);
+    assert_eq!(mbc5.read_range(0x3FFF, 0x10).unwrap().len(), 1);
+    assert_eq!(mbc5.read_range(0xBFF0, 0x20).unwrap().len(), 0x10);
+}
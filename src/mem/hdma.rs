@@ -0,0 +1,110 @@
+//! CGB VRAM DMA (HDMA1-HDMA5)
+//!
+//! General purpose transfers copy everything as soon as HDMA5 is written, HBlank transfers copy
+//! one 16 byte block at the start of every HBlank until they're done or cancelled.
+
+use crate::{
+    error::GBError,
+    mem::map::Memory,
+    state::{SaveState, StateReader, StateWriter},
+};
+
+const HDMA5: usize = 0x55;
+
+#[derive(Default)]
+pub struct Hdma {
+    pub src: u16,
+    pub dst: u16,
+    /// 16 byte blocks left in the current HBlank transfer
+    remaining: u8,
+    pub active: bool,
+}
+
+impl Hdma {
+    /// Handles writes to HDMA1-5, returns the number of blocks a general purpose transfer wants
+    /// copied right away
+    pub fn write(&mut self, mem: &mut Memory, addr: usize, value: u8) -> u8 {
+        match addr {
+            0xFF51 => self.src = (self.src & 0x00F0) | (value as u16) << 8,
+            0xFF52 => self.src = (self.src & 0xFF00) | (value & 0xF0) as u16,
+            0xFF53 => self.dst = (self.dst & 0x00F0) | ((value & 0x1F) as u16) << 8,
+            0xFF54 => self.dst = (self.dst & 0x1F00) | (value & 0xF0) as u16,
+            0xFF55 => {
+                let blocks = (value & 0x7F) + 1;
+                if self.active && value & 0x80 == 0 {
+                    // Writing bit 7 clear mid HBlank transfer cancels it
+                    self.active = false;
+                    mem.io[HDMA5] = 0x80 | (self.remaining - 1);
+                } else if value & 0x80 != 0 {
+                    self.active = true;
+                    self.remaining = blocks;
+                    mem.io[HDMA5] = blocks - 1;
+                } else {
+                    mem.io[HDMA5] = 0xFF;
+                    return blocks;
+                }
+            }
+            _ => (),
+        }
+        0
+    }
+
+    /// Copies the next 16 byte block, called for both transfer kinds
+    pub fn copy_block(&mut self, mem: &mut Memory) -> Result<(), GBError> {
+        for _ in 0..0x10 {
+            let value = mem.dma_read(self.src as usize)?;
+            mem.dma_write(0x8000 | (self.dst & 0x1FFF) as usize, value)?;
+            self.src = self.src.wrapping_add(1);
+            self.dst = self.dst.wrapping_add(1) & 0x1FFF;
+        }
+        if self.active {
+            self.remaining -= 1;
+            if self.remaining == 0 {
+                self.active = false;
+                mem.io[HDMA5] = 0xFF;
+            } else {
+                mem.io[HDMA5] = self.remaining - 1;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl SaveState for Hdma {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.u16(self.src);
+        w.u16(self.dst);
+        w.u8(self.remaining);
+        w.bool(self.active);
+    }
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), GBError> {
+        self.src = r.u16()?;
+        self.dst = r.u16()? & 0x1FFF;
+        self.remaining = r.u8()?;
+        self.active = r.bool()? && self.remaining != 0;
+        Ok(())
+    }
+}
+
+#[test]
+fn hblank_transfer_test() {
+    use crate::gameboy::GameBoy;
+    // LCDC = 0x91, 2 block HBlank DMA from 0x0200 to 0x8000, then a speed switch
+    let code = [
+        0x3E, 0x91, 0xE0, 0x40, 0x3E, 0x02, 0xE0, 0x51, 0x3E, 0x00, 0xE0, 0x52, 0x3E, 0x00, 0xE0,
+        0x53, 0x3E, 0x00, 0xE0, 0x54, 0x3E, 0x81, 0xE0, 0x55, 0x3E, 0x01, 0xE0, 0x4D, 0x10, 0x00,
+        0x18, 0xFE,
+    ];
+    let tiles: Vec<u8> = (1..=0x20).collect();
+    let rom = GameBoy::test_rom(&[(0x100, &code), (0x200, &tiles)]);
+    let mut gameboy = GameBoy::new(rom, GameBoy::test_cgb_header());
+    gameboy.run_frame().unwrap();
+    let memory = &gameboy.bus.memory;
+    let vram: Vec<u8> = (0x8000..0x8020)
+        .map(|addr| memory.vram_read(0, addr))
+        .collect();
+    assert_eq!(vram, tiles);
+    assert_eq!(memory.io[0x55], 0xFF);
+    assert!(gameboy.bus.double_speed);
+    assert_eq!(memory.io[0x4D], 0xFE);
+}
@@ -1,10 +1,69 @@
 use crate::{
     bus::Bus,
+    cdl,
+    cheats::Cheats,
+    cpu::reg_file::Modes,
     error::GBError,
-    mbc::{Mbc, MbcFactory, mbc1::MBC1, mbc2::MBC2, mbc3::MBC3},
-    rom::rom_info::ROMInfo,
+    mbc::{Mbc, MbcFactory, mbc1::MBC1, mbc2::MBC2, mbc3::MBC3, mbc5::MBC5},
+    rom::rom_info::{CGBMode, ROMInfo},
+    state::{SaveState, StateReader, StateWriter},
 };
 
+// https://gbdev.io/pandocs/Power_Up_Sequence.html#hardware-registers
+// (register, value) as the DMG boot ROM leaves them, CGB differences are applied on top
+const POST_BOOT_IO: [(usize, u8); 40] = [
+    (0x00, 0xCF), // P1
+    (0x01, 0x00), // SB
+    (0x02, 0x7E), // SC
+    (0x04, 0xAB), // DIV
+    (0x05, 0x00), // TIMA
+    (0x06, 0x00), // TMA
+    (0x07, 0xF8), // TAC
+    (0x0F, 0xE1), // IF
+    (0x10, 0x80), // NR10
+    (0x11, 0xBF), // NR11
+    (0x12, 0xF3), // NR12
+    (0x13, 0xFF), // NR13
+    (0x14, 0xBF), // NR14
+    (0x16, 0x3F), // NR21
+    (0x17, 0x00), // NR22
+    (0x18, 0xFF), // NR23
+    (0x19, 0xBF), // NR24
+    (0x1A, 0x7F), // NR30
+    (0x1B, 0xFF), // NR31
+    (0x1C, 0x9F), // NR32
+    (0x1D, 0xFF), // NR33
+    (0x1E, 0xBF), // NR34
+    (0x20, 0xFF), // NR41
+    (0x21, 0x00), // NR42
+    (0x22, 0x00), // NR43
+    (0x23, 0xBF), // NR44
+    (0x24, 0x77), // NR50
+    (0x25, 0xF3), // NR51
+    (0x26, 0xF1), // NR52
+    (0x40, 0x91), // LCDC
+    (0x41, 0x85), // STAT
+    (0x42, 0x00), // SCY
+    (0x43, 0x00), // SCX
+    (0x44, 0x00), // LY
+    (0x45, 0x00), // LYC
+    (0x46, 0xFF), // DMA
+    (0x47, 0xFC), // BGP
+    (0x48, 0xFF), // OBP0
+    (0x49, 0xFF), // OBP1
+    (0x4D, 0xFF), // KEY1
+];
+const CGB_POST_BOOT_IO: [(usize, u8); 8] = [
+    (0x02, 0x7F), // SC
+    (0x04, 0x00), // DIV
+    (0x46, 0x00), // DMA
+    (0x4D, 0x7E), // KEY1
+    (0x51, 0xFF), // HDMA1
+    (0x52, 0xFF), // HDMA2
+    (0x53, 0xFF), // HDMA3
+    (0x55, 0xFF), // HDMA5
+];
+
 #[derive(Debug)]
 pub struct Memory {
     vram: Vec<Vec<u8>>,
@@ -16,6 +75,17 @@ pub struct Memory {
     hram: Vec<u8>,
     pub ie: u8,
     pub controller: Box<dyn Mbc>,
+    /// Running in CGB mode, decided once from the cartridge header
+    pub cgb: bool,
+    /// CGB palette RAM, 8 palettes of 4 little endian RGB555 colors each
+    pub bg_palettes: Vec<u8>,
+    pub obj_palettes: Vec<u8>,
+    /// DMG (256 bytes) or CGB (2304 bytes) boot ROM, empty when booting straight into the game
+    boot_rom: Vec<u8>,
+    /// The boot ROM overlays the cartridge until something is written to 0xFF50
+    pub boot_rom_mapped: bool,
+    /// Game Genie patches apply to every ROM read, GameShark writes are up to the bus
+    pub cheats: Cheats,
 }
 
 impl Memory {
@@ -24,10 +94,12 @@ impl Memory {
             0x0..=0x3 => Box::new(MBC1::new(rom, header_data)),
             0x5..=0x6 => Box::new(MBC2::new(rom, header_data)),
             0xF..0x19 => Box::new(MBC3::new(rom, header_data)),
+            0x19..=0x1E => Box::new(MBC5::new(rom, header_data)),
             _ => todo!(),
         }
     }
     pub fn init_rom(rom: Vec<u8>, header_data: ROMInfo) -> Self {
+        let cgb = matches!(header_data.cgb, CGBMode::Color { .. });
         Self {
             vram: vec![vec![0; 0x2000]; 2],
             active_vram: 0,
@@ -38,12 +110,91 @@ impl Memory {
             hram: vec![0; 0x7F],
             ie: 0,
             controller: Self::create_controller(rom, header_data),
+            cgb,
+            // The boot ROM leaves every BG color white
+            bg_palettes: vec![0xFF; 0x40],
+            obj_palettes: vec![0; 0x40],
+            boot_rom: vec![],
+            boot_rom_mapped: false,
+            cheats: Cheats::default(),
+        }
+    }
+    /// Maps `boot_rom` over the cartridge, the CGB one also covers 0x0200-0x08FF
+    pub fn map_boot_rom(&mut self, boot_rom: Vec<u8>) {
+        // A DMG boot ROM means DMG hardware, even for CGB cartridges. The CGB one only gets here
+        // with CGB cartridges, see `GameBoy::with_boot_rom`
+        self.cgb &= boot_rom.len() > 0x100;
+        self.boot_rom = boot_rom;
+        self.boot_rom_mapped = true;
+        self.io[0x00] = 0xCF;
+    }
+    /// Sets the IO registers to what the boot ROM of `mode` leaves behind
+    pub fn init_post_boot_io(&mut self, mode: Modes) {
+        for (reg, value) in POST_BOOT_IO {
+            self.io[reg] = value;
+        }
+        if matches!(mode, Modes::CGB | Modes::CGBDMG) {
+            for (reg, value) in CGB_POST_BOOT_IO {
+                self.io[reg] = value;
+            }
+        }
+        if mode == Modes::CGBDMG {
+            // DMG compatibility mode locks KEY1
+            self.io[0x4D] = 0xFF;
+        }
+        self.set_vram_bank(0);
+        self.set_wram_bank(1);
+    }
+    /// VRAM access for the PPU, which ignores VBK
+    pub fn vram_read(&self, bank: usize, addr: usize) -> u8 {
+        self.vram[bank & 1][addr - 0x8000]
+    }
+    /// VBK, only bank 0 exists on DMG
+    pub fn set_vram_bank(&mut self, bank: u8) {
+        self.io[0x4F] = 0xFF;
+        if self.cgb {
+            self.active_vram = (bank & 1) as usize;
+            self.io[0x4F] = 0xFE | self.active_vram as u8;
         }
     }
+    /// BCPS/BCPD (0xFF68/9) and OCPS/OCPD (0xFF6A/B), data writes auto-increment the index when
+    /// bit 7 of the spec register is set
+    pub fn write_palette(&mut self, addr: usize, value: u8) {
+        let (spec, palettes) = if addr < 0xFF6A {
+            (0x68, &mut self.bg_palettes)
+        } else {
+            (0x6A, &mut self.obj_palettes)
+        };
+        if addr & 1 == 1 {
+            let index = (self.io[spec] & 0x3F) as usize;
+            palettes[index] = value;
+            if self.io[spec] & 0x80 != 0 {
+                self.io[spec] = 0x80 | ((index as u8 + 1) & 0x3F);
+            }
+        }
+        self.io[spec + 1] = palettes[(self.io[spec] & 0x3F) as usize];
+    }
+    /// SVBK, selecting bank 0 maps bank 1
+    pub fn set_wram_bank(&mut self, bank: u8) {
+        self.io[0x70] = 0xFF;
+        if self.cgb {
+            self.active_wram = ((bank & 7) as usize).max(1);
+            self.io[0x70] = 0xF8 | self.active_wram as u8;
+        }
+    }
+    /// Reads without side effects. Every single-byte cartridge read ends up here (CPU reads and
+    /// fetches, HDMA, the debugger), so it's the one place applying Game Genie patches over
+    /// [`Mbc::read`]. OAM DMA copies whole ranges out of the MBC and patches them itself
     pub fn dma_read(&self, addr: usize) -> Result<u8, GBError> {
         match addr {
-            0x0000..=0x3FFF => Some(self.controller.read(addr)),
-            0x4000..=0x7FFF => Some(self.controller.read(addr)),
+            0x0000..=0x00FF if self.boot_rom_mapped => self.boot_rom.get(addr).copied(),
+            0x0200..=0x08FF if self.boot_rom_mapped && self.boot_rom.len() > 0x100 => {
+                self.boot_rom.get(addr).copied()
+            }
+            0x0000..=0x7FFF => Some(
+                self.cheats
+                    .patch_rom(addr as u16, self.controller.read(addr)),
+            ),
             0x8000..=0x9FFF => self.vram[self.active_vram].get(addr - 0x8000).copied(),
             0xA000..=0xBFFF => Some(self.controller.read(addr)),
             0xC000..=0xCFFF => self.wram[0].get(addr - 0xC000).copied(),
@@ -59,6 +210,27 @@ impl Memory {
         }
         .ok_or(GBError::BadAddress(addr as u16))
     }
+    /// Offset into the ROM file a read of `addr` is served from, `None` outside of ROM and while
+    /// the boot ROM covers it
+    pub fn rom_offset(&self, addr: u16) -> Option<usize> {
+        let bank = match addr {
+            0x0000..=0x00FF if self.boot_rom_mapped => return None,
+            0x0200..=0x08FF if self.boot_rom_mapped && self.boot_rom.len() > 0x100 => return None,
+            0x0000..=0x3FFF => self.controller.zero_bank(),
+            0x4000..=0x7FFF => self.controller.rom_bank(),
+            _ => return None,
+        };
+        Some(bank * 0x4000 + (addr & 0x3FFF) as usize)
+    }
+    /// Writes every enabled GameShark code to RAM
+    pub fn apply_cheats(&mut self) {
+        let writes: Vec<_> = self.cheats.ram_writes().collect();
+        for (addr, value) in writes {
+            if let Err(e) = self.dma_write(addr as usize, value) {
+                log::warn!("GameShark: {e}");
+            }
+        }
+    }
     pub fn dma_write(&mut self, addr: usize, value: u8) -> Result<(), GBError> {
         let opt_mem_ptr: Option<&mut u8> = match addr {
             0x0000..=0x1FFF => {
@@ -104,15 +276,15 @@ impl Memory {
     pub fn oam_transfer(bus: &mut Bus, addr: u8) {
         let src_addr = addr as usize * 0x100;
         let slice = match src_addr {
-            0x0000..=0x3FFF => bus.memory.controller.read_range(src_addr, 0x9F),
-            0x4000..=0x7FFF => bus.memory.controller.read_range(src_addr, 0x9F),
+            0x0000..=0x3FFF => bus.memory.controller.read_range(src_addr, 0xA0),
+            0x4000..=0x7FFF => bus.memory.controller.read_range(src_addr, 0xA0),
             0xC000..=0xCFFF => {
                 let real_addr = src_addr - 0xC000;
                 Some(&bus.memory.wram[0][real_addr..=real_addr + 0x9F])
             }
             0xD000..=0xDFFF => {
                 let real_addr = src_addr - 0xD000;
-                Some(&bus.memory.wram[0][real_addr..=real_addr + 0x9F])
+                Some(&bus.memory.wram[bus.memory.active_wram][real_addr..=real_addr + 0x9F])
             }
             0xE000..=0xEFFF => {
                 let real_addr = src_addr - 0xE000;
@@ -123,8 +295,108 @@ impl Memory {
         if let Some(oam_data) = slice {
             bus.memory.oam.copy_from_slice(oam_data);
         }
+        if src_addr < 0x8000 {
+            for (i, byte) in bus.memory.oam.iter_mut().enumerate() {
+                *byte = bus.memory.cheats.patch_rom((src_addr + i) as u16, *byte);
+            }
+        }
+        if let Some(cdl) = bus.cdl.as_mut() {
+            for i in 0..0xA0 {
+                cdl.log(&bus.memory, (src_addr + i) as u16, cdl::DMA);
+            }
+        }
         for _ in 0..160 {
             bus.tick();
         }
     }
 }
+
+/// The cartridge is saved separately (see [`Mbc`])
+impl SaveState for Memory {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.banks(&self.vram);
+        w.u8(self.active_vram as u8);
+        w.banks(&self.wram);
+        w.u8(self.active_wram as u8);
+        w.bytes(&self.oam);
+        w.bytes(&self.io);
+        w.bytes(&self.hram);
+        w.u8(self.ie);
+        w.bytes(&self.bg_palettes);
+        w.bytes(&self.obj_palettes);
+        w.bool(self.boot_rom_mapped);
+    }
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), GBError> {
+        r.banks(&mut self.vram)?;
+        self.active_vram = r.u8()? as usize % self.vram.len();
+        r.banks(&mut self.wram)?;
+        self.active_wram = r.u8()? as usize % self.wram.len();
+        r.fill(&mut self.oam)?;
+        r.fill(&mut self.io)?;
+        r.fill(&mut self.hram)?;
+        self.ie = r.u8()?;
+        if r.version >= 3 {
+            r.fill(&mut self.bg_palettes)?;
+            r.fill(&mut self.obj_palettes)?;
+        }
+        if r.version >= 4 {
+            // The boot ROM itself isn't saved, it has to be the one given on startup
+            self.boot_rom_mapped = r.bool()? && !self.boot_rom.is_empty();
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn cgb_banking_test() {
+    use crate::gameboy::GameBoy;
+    let mut mem = Memory::init_rom(GameBoy::test_rom(&[]), GameBoy::test_cgb_header());
+    // BCPS with auto-increment wraps from the last byte back to the first
+    mem.io[0x68] = 0x80 | 0x3E;
+    mem.write_palette(0xFF69, 0x12);
+    mem.write_palette(0xFF69, 0x34);
+    mem.write_palette(0xFF69, 0x56);
+    assert_eq!(&mem.bg_palettes[0x3E..], [0x12, 0x34]);
+    assert_eq!(mem.bg_palettes[0], 0x56);
+    assert_eq!(mem.io[0x68], 0x81);
+    assert_eq!(mem.io[0x69], 0xFF);
+    // Without it OCPS stays put, selecting an index reads its byte back through OCPD
+    mem.io[0x6A] = 0x05;
+    mem.write_palette(0xFF6B, 0x77);
+    mem.write_palette(0xFF6B, 0x78);
+    assert_eq!(mem.io[0x6A], 0x05);
+    assert_eq!(mem.obj_palettes[5], 0x78);
+    mem.io[0x6A] = 0x04;
+    mem.write_palette(0xFF6A, 0x04);
+    assert_eq!(mem.io[0x6B], 0);
+    mem.io[0x6A] = 0x05;
+    mem.write_palette(0xFF6A, 0x05);
+    assert_eq!(mem.io[0x6B], 0x78);
+    assert!(mem.bg_palettes[1..0x3E].iter().all(|&byte| byte == 0xFF));
+
+    // VBK only looks at bit 0, the PPU reads either bank regardless
+    mem.dma_write(0x8000, 0xAA).unwrap();
+    mem.set_vram_bank(0xFF);
+    assert_eq!(mem.io[0x4F], 0xFF);
+    assert_eq!(mem.dma_read(0x8000).unwrap(), 0);
+    mem.dma_write(0x9FFF, 0xBB).unwrap();
+    mem.set_vram_bank(2);
+    assert_eq!(mem.io[0x4F], 0xFE);
+    assert_eq!(mem.dma_read(0x8000).unwrap(), 0xAA);
+    assert_eq!(mem.dma_read(0x9FFF).unwrap(), 0);
+    assert_eq!(
+        (mem.vram_read(0, 0x8000), mem.vram_read(1, 0x8000)),
+        (0xAA, 0)
+    );
+    assert_eq!(
+        (mem.vram_read(0, 0x9FFF), mem.vram_read(1, 0x9FFF)),
+        (0, 0xBB)
+    );
+
+    // There's a single bank on DMG
+    let mut mem = Memory::init_rom(GameBoy::test_rom(&[]), GameBoy::test_header());
+    mem.set_vram_bank(1);
+    assert_eq!(mem.io[0x4F], 0xFF);
+    mem.dma_write(0x8000, 0xCC).unwrap();
+    assert_eq!(mem.vram_read(0, 0x8000), 0xCC);
+}
@@ -1 +1,2 @@
+pub mod hdma;
 pub mod map;
@@ -0,0 +1,111 @@
+//! Frame pacing for frontends: fast-forward, pause and frame advance
+//!
+//! The core has no notion of time, [`GameBoy::run_frame`] runs as fast as the host allows.
+//! A frontend presents a frame every ~16.7 ms and asks [`Pacing::frames_due`] how many frames
+//! to emulate in between.
+//!
+//! [`GameBoy::run_frame`]: crate::gameboy::GameBoy::run_frame
+
+use std::time::Duration;
+
+/// Time between two frames on hardware, 70224 cycles at 4.194304 MHz (~59.73 Hz)
+pub const FRAME_TIME: Duration = Duration::from_nanos(16_742_706);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Speed {
+    #[default]
+    Normal,
+    /// N frames per presented frame
+    Times(u32),
+    /// As many frames as fit in a presented frame
+    Uncapped,
+}
+
+#[derive(Debug, Default)]
+pub struct Pacing {
+    /// Speed used while fast-forwarding
+    pub turbo: Speed,
+    fast_forward: bool,
+    paused: bool,
+    advance: u32,
+}
+
+impl Pacing {
+    pub fn new(turbo: Speed) -> Self {
+        Self {
+            turbo,
+            ..Self::default()
+        }
+    }
+
+    pub fn speed(&self) -> Speed {
+        if self.fast_forward && !self.paused {
+            self.turbo
+        } else {
+            Speed::Normal
+        }
+    }
+
+    pub fn set_fast_forward(&mut self, fast_forward: bool) {
+        self.fast_forward = fast_forward;
+    }
+
+    pub fn is_fast_forward(&self) -> bool {
+        self.speed() != Speed::Normal
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+        self.advance = 0;
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.set_paused(!self.paused);
+    }
+
+    /// Pauses, then lets exactly one more frame through
+    pub fn frame_advance(&mut self) {
+        self.paused = true;
+        self.advance += 1;
+    }
+
+    /// Frames to emulate before presenting the next one, `None` means keep going until the frame
+    /// time is used up
+    pub fn frames_due(&mut self) -> Option<u32> {
+        if self.paused {
+            return Some(std::mem::take(&mut self.advance));
+        }
+        match self.speed() {
+            Speed::Normal => Some(1),
+            Speed::Times(n) => Some(n),
+            Speed::Uncapped => None,
+        }
+    }
+
+    /// Audio only plays at normal speed, fast-forwarded samples would overflow the output
+    pub fn plays_audio(&self) -> bool {
+        !self.paused && self.speed() == Speed::Normal
+    }
+}
+
+#[test]
+fn pacing_test() {
+    let mut pacing = Pacing::new(Speed::Times(4));
+    assert_eq!(pacing.frames_due(), Some(1));
+    pacing.set_fast_forward(true);
+    assert_eq!(pacing.frames_due(), Some(4));
+    assert!(!pacing.plays_audio());
+    pacing.toggle_pause();
+    assert_eq!(pacing.frames_due(), Some(0));
+    pacing.frame_advance();
+    pacing.frame_advance();
+    assert_eq!(pacing.frames_due(), Some(2));
+    assert_eq!(pacing.frames_due(), Some(0));
+    pacing.toggle_pause();
+    pacing.turbo = Speed::Uncapped;
+    assert_eq!(pacing.frames_due(), None);
+}
@@ -5,6 +5,7 @@ use crate::{
     error::GBError,
     mem::map::Memory,
     ppu::{ppumode::DrawLayer, sprite::GBSprite},
+    state::{SaveState, StateReader, StateWriter},
 };
 
 const LCDC: usize = 0x40;
@@ -17,7 +18,7 @@ const SCX: usize = 0x43;
 const WY: usize = 0x4A;
 const WX: usize = 0x4B;
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Default)]
 pub struct Pixel {
     pub color_id: u8,
     pub palette: u8,
@@ -26,34 +27,19 @@ pub struct Pixel {
     pub obj_priority: Option<u8>,
 }
 
+#[derive(Default)]
 pub struct Fetcher {
     current_tile_id: u8,
-    tile_x_step: u8,
-    tile_y_step: u8,
     pub phase: u8,
     pub lx: u8,
     pub window_ly: u16,
     tile_hi: u8,
     tile_lo: u8,
+    /// CGB BG map attributes of the current tile (palette, bank, flips, priority)
+    tile_attr: u8,
     pub current_sprite: Option<GBSprite>,
 }
 
-impl Default for Fetcher {
-    fn default() -> Self {
-        Self {
-            current_tile_id: 0,
-            tile_x_step: 0,
-            tile_y_step: 0,
-            phase: 0,
-            tile_hi: 0,
-            tile_lo: 0,
-            lx: 0,
-            window_ly: 0,
-            current_sprite: None,
-        }
-    }
-}
-
 impl Fetcher {
     pub fn fetch_bg_tile(&mut self, mem: &Memory, draw_layer: &DrawLayer) -> Result<u8, GBError> {
         let scy = mem.io[SCY];
@@ -81,7 +67,10 @@ impl Fetcher {
                 base | tile_map | tile_map_y | tile_map_x
             }
         };
-        self.current_tile_id = mem.dma_read(tile_addr as usize)?;
+        self.current_tile_id = mem.vram_read(0, tile_addr as usize);
+        if mem.cgb {
+            self.tile_attr = mem.vram_read(1, tile_addr as usize);
+        }
         self.phase = (self.phase + 1) & 3;
         Ok(2)
     }
@@ -104,10 +93,11 @@ impl Fetcher {
                 sprite.tile_index.saturating_add(0)
             };
             let addr = 0x8000 + (tile_id as usize * 16) + (2 * tile_row as usize);
+            let bank = if mem.cgb { sprite.bank as usize } else { 0 };
             if self.phase == 1 {
-                self.tile_lo = mem.dma_read(addr).unwrap();
+                self.tile_lo = mem.vram_read(bank, addr);
             } else {
-                self.tile_hi = mem.dma_read(addr + 1).unwrap();
+                self.tile_hi = mem.vram_read(bank, addr + 1);
             }
             self.phase = (self.phase + 1) & 3;
             return Ok(0);
@@ -120,7 +110,7 @@ impl Fetcher {
             0x9000
         };
         let is_window = *draw_layer == DrawLayer::Window;
-        let tile_row = if is_window {
+        let mut tile_row = if is_window {
             if ly < wy as usize {
                 return Ok(0);
             }
@@ -128,15 +118,22 @@ impl Fetcher {
         } else {
             (ly + scy) & 7
         };
+        let mut bank = 0;
+        if mem.cgb {
+            bank = alu::read_bits(self.tile_attr, 3, 1) as usize;
+            if alu::read_bits(self.tile_attr, 6, 1) == 1 {
+                tile_row = 7 - tile_row;
+            }
+        }
         let addr = if base_ptr == 0x8000 {
             base_ptr + (16_usize * self.current_tile_id as usize)
         } else {
             (base_ptr as isize + (16_isize * self.current_tile_id as i8 as isize)) as usize
         } + (2 * tile_row);
         if self.phase == 1 {
-            self.tile_lo = mem.dma_read(addr).unwrap();
+            self.tile_lo = mem.vram_read(bank, addr);
         } else {
-            self.tile_hi = mem.dma_read(addr + 1).unwrap();
+            self.tile_hi = mem.vram_read(bank, addr + 1);
         }
         self.phase = (self.phase + 1) & 3;
         Ok(2)
@@ -145,11 +142,14 @@ impl Fetcher {
         if (!fifo.is_empty() && self.current_sprite.is_none()) || self.phase != 3 {
             return;
         }
+        let bg_x_flip = mem.cgb && alu::read_bits(self.tile_attr, 5, 1) == 1;
         for i in (0..8).rev() {
             let color_id = {
-                if let Some(sprite) = self.current_sprite
-                    && sprite.x_flip
-                {
+                let x_flip = match self.current_sprite {
+                    Some(sprite) => sprite.x_flip,
+                    None => bg_x_flip,
+                };
+                if x_flip {
                     let lo = alu::read_bits(self.tile_lo, 7 - i, 1);
                     let hi = alu::read_bits(self.tile_hi, 7 - i, 1) << 1;
                     hi | lo
@@ -161,38 +161,40 @@ impl Fetcher {
             };
 
             if let Some(sprite) = self.current_sprite {
-                if (fifo
-                    .get(7 - i as usize)
-                    .is_some_and(|pixel| pixel.color_id != 0)
-                    && color_id == 0)
-                    || ((sprite.x + fifo_lx as i16 + (7 - i as i16)) < 0)
+                // CGB resolves overlapping sprites by OAM index instead of X
+                if (fifo.get(7 - i as usize).is_some_and(|pixel| {
+                    pixel.color_id != 0
+                        && (color_id == 0
+                            || (mem.cgb && pixel.cgb_priority < Some(sprite.oam_index)))
+                })) || ((sprite.x + fifo_lx as i16 + (7 - i as i16)) < 0)
                 {
                     continue;
                 }
+                let pixel = Pixel {
+                    color_id,
+                    palette: if mem.cgb {
+                        sprite.cgb_palette
+                    } else {
+                        mem.io[if sprite.dmg_palette == 0 { OBP0 } else { OBP1 }]
+                    },
+                    cgb_priority: mem.cgb.then_some(sprite.oam_index),
+                    bg_priority: None,
+                    obj_priority: Some(sprite.priority),
+                };
                 match fifo.get_mut(7 - i as usize) {
-                    Some(pixel) => {
-                        *pixel = Pixel {
-                            color_id,
-                            palette: mem.io[if sprite.dmg_palette == 0 { OBP0 } else { OBP1 }],
-                            cgb_priority: None,
-                            bg_priority: None,
-                            obj_priority: Some(sprite.priority),
-                        }
-                    }
-                    None => fifo.push_back(Pixel {
-                        color_id,
-                        palette: mem.io[if sprite.dmg_palette == 0 { OBP0 } else { OBP1 }],
-                        cgb_priority: None,
-                        bg_priority: None,
-                        obj_priority: Some(sprite.priority),
-                    }),
+                    Some(old) => *old = pixel,
+                    None => fifo.push_back(pixel),
                 }
             } else {
                 fifo.push_back(Pixel {
                     color_id,
-                    palette: mem.io[BGP],
+                    palette: if mem.cgb {
+                        alu::read_bits(self.tile_attr, 0, 3)
+                    } else {
+                        mem.io[BGP]
+                    },
                     cgb_priority: None,
-                    bg_priority: None,
+                    bg_priority: mem.cgb.then(|| alu::read_bits(self.tile_attr, 7, 1)),
                     obj_priority: None,
                 });
                 self.lx = self.lx.saturating_add(1);
@@ -206,3 +208,113 @@ impl Fetcher {
         self.phase = 0;
     }
 }
+
+impl SaveState for Pixel {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.u8(self.color_id);
+        w.u8(self.palette);
+        w.opt_u8(self.cgb_priority);
+        w.opt_u8(self.bg_priority);
+        w.opt_u8(self.obj_priority);
+    }
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), GBError> {
+        self.color_id = r.u8()? & 3;
+        self.palette = r.u8()?;
+        self.cgb_priority = r.opt_u8()?;
+        self.bg_priority = r.opt_u8()?;
+        self.obj_priority = r.opt_u8()?;
+        Ok(())
+    }
+}
+
+impl SaveState for Fetcher {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.u8(self.current_tile_id);
+        w.u8(self.phase);
+        w.u8(self.lx);
+        w.u16(self.window_ly);
+        w.u8(self.tile_hi);
+        w.u8(self.tile_lo);
+        w.bool(self.current_sprite.is_some());
+        if let Some(sprite) = &self.current_sprite {
+            sprite.save_state(w);
+        }
+        w.u8(self.tile_attr);
+    }
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), GBError> {
+        self.current_tile_id = r.u8()?;
+        self.phase = r.u8()? & 3;
+        self.lx = r.u8()?;
+        self.window_ly = r.u16()?;
+        self.tile_hi = r.u8()?;
+        self.tile_lo = r.u8()?;
+        self.current_sprite = if r.bool()? {
+            let mut sprite = GBSprite::default();
+            sprite.load_state(r)?;
+            Some(sprite)
+        } else {
+            None
+        };
+        if r.version >= 3 {
+            self.tile_attr = r.u8()?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn bg_attributes_test() {
+    use crate::gameboy::GameBoy;
+    let mut mem = Memory::init_rom(GameBoy::test_rom(&[]), GameBoy::test_cgb_header());
+    // Tiles at 0x8000, map at 0x9800, line 0
+    mem.io[LCDC] = 0x91;
+    // Tile 1 row 0 is solid in bank 0, row 7 of bank 1 has color 1 then color 2 on the left
+    mem.dma_write(0x9800, 1).unwrap();
+    mem.dma_write(0x8010, 0xFF).unwrap();
+    mem.dma_write(0x8011, 0xFF).unwrap();
+    mem.set_vram_bank(1);
+    mem.dma_write(0x801E, 0x80).unwrap();
+    mem.dma_write(0x801F, 0x40).unwrap();
+    let fetch = |mem: &Memory, attr: u8| -> Vec<Pixel> {
+        // Left over from the previous tile, only CGB mode replaces it
+        let mut fetcher = Fetcher {
+            tile_attr: attr,
+            ..Fetcher::default()
+        };
+        let mut fifo = VecDeque::new();
+        fetcher.fetch_bg_tile(mem, &DrawLayer::Bg).unwrap();
+        fetcher.fetch_tile_data(mem, &DrawLayer::Bg).unwrap();
+        fetcher.fetch_tile_data(mem, &DrawLayer::Bg).unwrap();
+        fetcher.push_to_fifo(mem, &mut fifo, 0);
+        fifo.into()
+    };
+    let colors = |pixels: &[Pixel]| -> Vec<u8> { pixels.iter().map(|p| p.color_id).collect() };
+
+    // Plain attributes come from the map in bank 1
+    mem.dma_write(0x9800, 0x00).unwrap();
+    assert_eq!(colors(&fetch(&mem, 0)), [3; 8]);
+    // Bank 1, Y flip, palette 5
+    mem.dma_write(0x9800, 0x4D).unwrap();
+    let pixels = fetch(&mem, 0);
+    assert_eq!(colors(&pixels), [1, 2, 0, 0, 0, 0, 0, 0]);
+    assert!(
+        pixels
+            .iter()
+            .all(|p| p.palette == 5 && p.bg_priority == Some(0))
+    );
+    // Adding X flip and BG-to-OBJ priority
+    mem.dma_write(0x9800, 0xED).unwrap();
+    let pixels = fetch(&mem, 0);
+    assert_eq!(colors(&pixels), [0, 0, 0, 0, 0, 0, 2, 1]);
+    assert!(
+        pixels
+            .iter()
+            .all(|p| p.palette == 5 && p.bg_priority == Some(1))
+    );
+
+    // DMG ignores bank 1 entirely
+    mem.cgb = false;
+    let pixels = fetch(&mem, 0xED);
+    assert_eq!(colors(&pixels), [3; 8]);
+    assert!(pixels.iter().all(|p| p.bg_priority.is_none()));
+}
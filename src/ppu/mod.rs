@@ -1,4 +1,5 @@
 pub mod fetcher;
+#[allow(clippy::module_inception)]
 pub mod ppu;
 pub mod ppumode;
 pub mod sprite;
@@ -1,17 +1,14 @@
-extern crate sdl3;
-
-use std::cmp::{self, Ordering};
 use std::collections::VecDeque;
 
 use log::trace;
 
-use crate::bus::Bus;
 use crate::cpu::alu;
 use crate::error::GBError;
 use crate::mem::map::Memory;
 use crate::ppu::fetcher::{Fetcher, Pixel};
 use crate::ppu::ppumode::*;
 use crate::ppu::sprite::GBSprite;
+use crate::state::{SaveState, StateReader, StateWriter};
 
 const IF: usize = 0x0F;
 const LCDC: usize = 0x40;
@@ -29,6 +26,10 @@ pub struct PPU {
     window_start_flag: bool,
     pub framebuffer: Vec<u8>,
     pub frame_flag: bool,
+    /// Set when a line enters HBlank, drives HBlank DMA
+    pub hblank_flag: bool,
+    /// Set when a frame enters VBlank, applies GameShark cheats
+    pub vblank_flag: bool,
     current_oam: VecDeque<GBSprite>,
     bg_fifo: VecDeque<Pixel>,
     oam_fifo: VecDeque<Pixel>,
@@ -41,6 +42,8 @@ impl Default for PPU {
     fn default() -> Self {
         Self {
             frame_flag: false,
+            hblank_flag: false,
+            vblank_flag: false,
             mode: PPUMode::Scan,
             last_cycle: 0,
             lx: 0,
@@ -68,6 +71,28 @@ impl PPU {
             mem.io[STAT] = alu::set_bit(mem.io[STAT], 2, false);
         }
     }
+    /// CGB pixel mixing, LCDC bit 0 clear puts every sprite above the BG instead of hiding it
+    fn colorise_cgb(mem: &Memory, bg_pixel: &Pixel, obj_pixel: &Option<Pixel>) -> [u8; 3] {
+        let master_priority = alu::read_bits(mem.io[LCDC], 0, 1) == 1;
+        let (palettes, pixel) = match obj_pixel {
+            Some(obj)
+                if obj.color_id != 0
+                    && (!master_priority
+                        || bg_pixel.color_id == 0
+                        || (bg_pixel.bg_priority != Some(1) && obj.obj_priority != Some(1))) =>
+            {
+                (&mem.obj_palettes, obj)
+            }
+            _ => (&mem.bg_palettes, bg_pixel),
+        };
+        let index = (pixel.palette as usize & 7) * 8 + pixel.color_id as usize * 2;
+        let color = u16::from_le_bytes([palettes[index], palettes[index + 1]]);
+        // Scale each 5-bit channel up to 8 bits
+        [0, 5, 10].map(|shift| {
+            let channel = ((color >> shift) & 0x1F) as u8;
+            (channel << 3) | (channel >> 2)
+        })
+    }
     fn colorise(bg_pixel: &Pixel, obj_pixel: &Option<Pixel>) -> [u8; 3] {
         let visible_pixel = if let Some(sprite_pixel) = obj_pixel
             && sprite_pixel.color_id != 0
@@ -128,6 +153,7 @@ impl PPU {
 
                 if self.lx > 159 {
                     self.mode = PPUMode::HBlank;
+                    self.hblank_flag = true;
                     self.bg_fifo.clear();
                     self.oam_fifo.clear();
                     self.current_oam.clear();
@@ -160,6 +186,7 @@ impl PPU {
                 self.fetcher.window_ly = 0;
                 if mem.io[LY] == 144 {
                     self.frame_flag = true;
+                    self.vblank_flag = true;
                     self.mode.stat_interrupt(mem);
                     mem.io[IF] = alu::set_bit(mem.io[IF], 0, true);
                 } else if mem.io[LY] == 153 {
@@ -215,12 +242,10 @@ impl PPU {
                 (DrawLayer::Bg, true) => {
                     self.mode = PPUMode::Draw(DrawLayer::Bg);
                 }
-                (DrawLayer::Obj(sprite), true) => {
-                    if alu::read_bits(mem.io[LCDC], 1, 1) == 1 {
-                        self.fetcher.switch_to_sprite(sprite);
-                        self.mode = PPUMode::Draw(layer_query.0);
-                        return;
-                    }
+                (DrawLayer::Obj(sprite), true) if alu::read_bits(mem.io[LCDC], 1, 1) == 1 => {
+                    self.fetcher.switch_to_sprite(sprite);
+                    self.mode = PPUMode::Draw(layer_query.0);
+                    return;
                 }
                 _ => (),
             }
@@ -234,12 +259,16 @@ impl PPU {
             }
             if self.discard_counter == 0 {
                 let framebuffer_index = ((mem.io[LY] as usize * 160) + self.lx as usize) * 3;
-                if (alu::read_bits(mem.io[LCDC], 0, 1) == 0
+                if (!mem.cgb
+                    && alu::read_bits(mem.io[LCDC], 0, 1) == 0
                     && self.mode == PPUMode::Draw(DrawLayer::Bg))
                     || alu::read_bits(mem.io[LCDC], 7, 1) == 0
                 {
                     self.framebuffer[framebuffer_index..framebuffer_index + 3]
                         .copy_from_slice(&[0xFF, 0xFF, 0xFF]);
+                } else if mem.cgb {
+                    self.framebuffer[framebuffer_index..framebuffer_index + 3]
+                        .copy_from_slice(&PPU::colorise_cgb(mem, &bg_pixel, &obj_pixel));
                 } else {
                     self.framebuffer[framebuffer_index..framebuffer_index + 3]
                         .copy_from_slice(&PPU::colorise(&bg_pixel, &obj_pixel));
@@ -282,11 +311,64 @@ impl PPU {
                     dmg_palette: alu::read_bits(attributes, 4, 1),
                     bank: alu::read_bits(attributes, 3, 1),
                     cgb_palette: alu::read_bits(attributes, 0, 3),
+                    oam_index: ((obj_addr - 0xFE00) / 4) as u8,
                 });
                 index += 1;
             }
         }
-        sprite_table.sort_by(|sprite_a, sprite_b| sprite_a.x.cmp(&sprite_b.x));
+        sprite_table.sort_by_key(|sprite| sprite.x);
         Ok(sprite_table)
     }
 }
+
+fn save_queue<T: SaveState>(w: &mut StateWriter, queue: &VecDeque<T>) {
+    w.u32(queue.len() as u32);
+    for item in queue {
+        item.save_state(w);
+    }
+}
+
+fn load_queue<T: SaveState + Default>(
+    r: &mut StateReader,
+    queue: &mut VecDeque<T>,
+) -> Result<(), GBError> {
+    queue.clear();
+    for _ in 0..r.u32()? {
+        let mut item = T::default();
+        item.load_state(r)?;
+        queue.push_back(item);
+    }
+    Ok(())
+}
+
+impl SaveState for PPU {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.u64(self.last_cycle);
+        self.mode.save_state(w);
+        w.u8(self.lx);
+        w.bool(self.window_start_flag);
+        w.bytes(&self.framebuffer);
+        w.bool(self.frame_flag);
+        save_queue(w, &self.current_oam);
+        save_queue(w, &self.bg_fifo);
+        save_queue(w, &self.oam_fifo);
+        self.fetcher.save_state(w);
+        w.u8(self.discard_counter);
+        w.u64(self.cycle_deficit);
+    }
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), GBError> {
+        self.last_cycle = r.u64()?;
+        self.mode.load_state(r)?;
+        self.lx = r.u8()?;
+        self.window_start_flag = r.bool()?;
+        r.fill(&mut self.framebuffer)?;
+        self.frame_flag = r.bool()?;
+        load_queue(r, &mut self.current_oam)?;
+        load_queue(r, &mut self.bg_fifo)?;
+        load_queue(r, &mut self.oam_fifo)?;
+        self.fetcher.load_state(r)?;
+        self.discard_counter = r.u8()?;
+        self.cycle_deficit = r.u64()?;
+        Ok(())
+    }
+}
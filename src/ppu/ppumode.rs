@@ -1,4 +1,10 @@
-use crate::{cpu::alu, mem::map::Memory, ppu::sprite::GBSprite};
+use crate::{
+    cpu::alu,
+    error::GBError,
+    mem::map::Memory,
+    ppu::sprite::GBSprite,
+    state::{SaveState, StateReader, StateWriter},
+};
 
 const STAT: usize = 0x41;
 const IF: usize = 0x0F;
@@ -57,3 +63,35 @@ impl PPUMode {
         }
     }
 }
+
+impl SaveState for PPUMode {
+    fn save_state(&self, w: &mut StateWriter) {
+        match self {
+            PPUMode::HBlank => w.u8(0),
+            PPUMode::VBlank => w.u8(1),
+            PPUMode::Scan => w.u8(2),
+            PPUMode::Draw(DrawLayer::Bg) => w.u8(3),
+            PPUMode::Draw(DrawLayer::Window) => w.u8(4),
+            PPUMode::Draw(DrawLayer::Obj(sprite)) => {
+                w.u8(5);
+                sprite.save_state(w);
+            }
+        }
+    }
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), GBError> {
+        *self = match r.u8()? {
+            0 => PPUMode::HBlank,
+            1 => PPUMode::VBlank,
+            2 => PPUMode::Scan,
+            3 => PPUMode::Draw(DrawLayer::Bg),
+            4 => PPUMode::Draw(DrawLayer::Window),
+            5 => {
+                let mut sprite = GBSprite::default();
+                sprite.load_state(r)?;
+                PPUMode::Draw(DrawLayer::Obj(sprite))
+            }
+            _ => return Err(GBError::InvalidState),
+        };
+        Ok(())
+    }
+}
@@ -1,4 +1,9 @@
-#[derive(Clone, Copy, PartialEq, PartialOrd)]
+use crate::{
+    error::GBError,
+    state::{SaveState, StateReader, StateWriter},
+};
+
+#[derive(Clone, Copy, PartialEq, PartialOrd, Default)]
 pub struct GBSprite {
     pub x: i16,
     pub y: i16,
@@ -9,4 +14,36 @@ pub struct GBSprite {
     pub dmg_palette: u8,
     pub cgb_palette: u8,
     pub bank: u8,
+    /// Position in OAM, decides which sprite wins overlaps in CGB mode
+    pub oam_index: u8,
+}
+
+impl SaveState for GBSprite {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.u16(self.x as u16);
+        w.u16(self.y as u16);
+        w.u8(self.tile_index);
+        w.u8(self.priority);
+        w.bool(self.y_flip);
+        w.bool(self.x_flip);
+        w.u8(self.dmg_palette);
+        w.u8(self.cgb_palette);
+        w.u8(self.bank);
+        w.u8(self.oam_index);
+    }
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), GBError> {
+        self.x = r.u16()? as i16;
+        self.y = r.u16()? as i16;
+        self.tile_index = r.u8()?;
+        self.priority = r.u8()?;
+        self.y_flip = r.bool()?;
+        self.x_flip = r.bool()?;
+        self.dmg_palette = r.u8()?;
+        self.cgb_palette = r.u8()?;
+        self.bank = r.u8()?;
+        if r.version >= 3 {
+            self.oam_index = r.u8()?;
+        }
+        Ok(())
+    }
 }
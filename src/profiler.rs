@@ -0,0 +1,206 @@
+//! Exact execution profiler
+//!
+//! Every instruction's T-cycles (including any interrupt dispatch right after it) go to its
+//! (bank, PC) and to the current call stack. Stacks are inferred from CALL, RST, interrupt
+//! entries and RET/RETI, a return pops every frame called at or below the stack pointer it
+//! returns to, so code that drops return addresses or jumps through `push`/`ret` doesn't leave
+//! frames behind forever.
+//!
+//! The call stacks are written in the folded format of Brendan Gregg's FlameGraph scripts
+//! (`frame;frame;frame cycles` per line), which inferno, speedscope and friends read too.
+
+use std::{collections::HashMap, io::Write};
+
+use crate::{mem::map::Memory, symbols::Symbols};
+
+/// Deeper calls are counted towards the deepest frame, for runaway recursion
+const MAX_DEPTH: usize = 256;
+const INTERRUPTS: [&str; 5] = ["VBlank", "STAT", "Timer", "Serial", "Joypad"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Frame {
+    bank: usize,
+    addr: u16,
+    interrupt: bool,
+}
+
+struct Node {
+    frame: Frame,
+    parent: usize,
+    cycles: u64,
+    children: HashMap<Frame, usize>,
+}
+
+pub struct Profiler {
+    /// Call tree, node 0 is the root
+    nodes: Vec<Node>,
+    /// Active frames as (node, SP right after the return address was pushed)
+    stack: Vec<(usize, u16)>,
+    /// T-cycles per (bank, PC) of the instruction that spent them
+    by_pc: HashMap<(usize, u16), u64>,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self {
+            nodes: vec![Node {
+                frame: Frame {
+                    bank: 0,
+                    addr: 0x100,
+                    interrupt: false,
+                },
+                parent: 0,
+                cycles: 0,
+                children: HashMap::new(),
+            }],
+            stack: vec![],
+            by_pc: HashMap::new(),
+        }
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The call tree node cycles are currently attributed to
+    pub fn current(&self) -> usize {
+        self.stack.last().map_or(0, |&(node, _)| node)
+    }
+
+    /// Adds `cycles` spent by the instruction at `bank`:`pc` while `node` was the current frame
+    pub fn attribute(&mut self, node: usize, bank: usize, pc: u16, cycles: u64) {
+        self.nodes[node].cycles += cycles;
+        *self.by_pc.entry((bank, pc)).or_default() += cycles;
+    }
+
+    /// A CALL or RST to `target` (or an interrupt dispatch, with `interrupt`) pushed its return
+    /// address, `sp` is the stack pointer after the push
+    pub fn call(&mut self, mem: &Memory, target: u16, sp: u16, interrupt: bool) {
+        if self.stack.len() >= MAX_DEPTH {
+            return;
+        }
+        let frame = Frame {
+            bank: bank(mem, target),
+            addr: target,
+            interrupt,
+        };
+        let parent = self.current();
+        let next = self.nodes.len();
+        let node = *self.nodes[parent].children.entry(frame).or_insert(next);
+        if node == next {
+            self.nodes.push(Node {
+                frame,
+                parent,
+                cycles: 0,
+                children: HashMap::new(),
+            });
+        }
+        self.stack.push((node, sp));
+    }
+
+    /// A RET or RETI popped its return address, `sp` is the stack pointer after the pop
+    pub fn ret(&mut self, sp: u16) {
+        while self
+            .stack
+            .last()
+            .is_some_and(|&(_, frame_sp)| frame_sp < sp)
+        {
+            self.stack.pop();
+        }
+    }
+
+    pub fn total_cycles(&self) -> u64 {
+        self.nodes.iter().map(|node| node.cycles).sum()
+    }
+
+    /// The `count` (bank, PC) pairs that spent the most T-cycles, most expensive first
+    pub fn hotspots(&self, count: usize) -> Vec<((usize, u16), u64)> {
+        let mut hotspots: Vec<_> = self
+            .by_pc
+            .iter()
+            .map(|(&pc, &cycles)| (pc, cycles))
+            .collect();
+        hotspots.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        hotspots.truncate(count);
+        hotspots
+    }
+
+    /// Writes one `root;caller;callee cycles` line per call stack that spent any cycles, frames
+    /// are named by their label if `symbols` has one
+    pub fn write_folded(&self, mut out: impl Write, symbols: &Symbols) -> std::io::Result<()> {
+        let names: Vec<String> = self
+            .nodes
+            .iter()
+            .enumerate()
+            .map(|(index, node)| frame_name(index, node.frame, symbols))
+            .collect();
+        for (index, node) in self.nodes.iter().enumerate() {
+            if node.cycles == 0 {
+                continue;
+            }
+            let mut path = vec![names[index].as_str()];
+            let mut parent = index;
+            while parent != 0 {
+                parent = self.nodes[parent].parent;
+                path.push(&names[parent]);
+            }
+            path.reverse();
+            writeln!(out, "{} {}", path.join(";"), node.cycles)?;
+        }
+        out.flush()
+    }
+}
+
+/// ROM bank `addr` is currently served from, 0 outside of ROM
+pub fn bank(mem: &Memory, addr: u16) -> usize {
+    mem.rom_offset(addr).map_or(0, |offset| offset / 0x4000)
+}
+
+fn frame_name(index: usize, frame: Frame, symbols: &Symbols) -> String {
+    if index == 0 {
+        return "root".to_string();
+    }
+    if let Some(label) = symbols.label(frame.addr, frame.bank) {
+        return label;
+    }
+    match frame.addr {
+        0x40..=0x60 if frame.interrupt => INTERRUPTS[(frame.addr as usize - 0x40) / 8].to_string(),
+        _ => format!("{:02X}:{:04X}", frame.bank, frame.addr),
+    }
+}
+
+#[test]
+fn profiler_test() {
+    use crate::gameboy::GameBoy;
+    let mut gameboy = GameBoy::test_with_patches(&[
+        // call 0x0200 | rst 0x08 | jr -6
+        (0x100, &[0xCD, 0x00, 0x02, 0xCF, 0x18, 0xFA]),
+        // 0x0008: ret
+        (0x08, &[0xC9]),
+        // 0x0200: nop | call 0x0210 | ret | 0x0210: pop hl | jp 0x0204
+        (0x200, &[0x00, 0xCD, 0x10, 0x02, 0xC9]),
+        (0x210, &[0xE1, 0xC3, 0x04, 0x02]),
+    ]);
+    gameboy.bus.profiler = Some(Profiler::new());
+    gameboy.run_frame().unwrap();
+    let profiler = gameboy.bus.profiler.as_ref().unwrap();
+    assert_eq!(profiler.total_cycles(), gameboy.bus.t_cycles);
+
+    let symbols = Symbols::parse("00:0200 Update\n");
+    let mut folded = vec![];
+    profiler.write_folded(&mut folded, &symbols).unwrap();
+    let folded = String::from_utf8(folded).unwrap();
+    let stacks: Vec<&str> = folded
+        .lines()
+        .map(|line| line.rsplit_once(' ').unwrap().0)
+        .collect();
+    assert!(stacks.contains(&"root"));
+    assert!(stacks.contains(&"root;Update"));
+    assert!(stacks.contains(&"root;Update;Update+16"));
+    assert!(stacks.contains(&"root;00:0008"));
+    // 0x0210 dropped its return address and the ret at 0x0204 returned past both frames
+    assert!(stacks.iter().all(|stack| stack.split(';').count() <= 3));
+    assert_eq!(profiler.hotspots(1)[0].0.1 & 0xFF00, 0x0100);
+}
@@ -0,0 +1,158 @@
+//! Cheat finder: narrows WRAM, HRAM and cartridge RAM down to the addresses holding a value
+//!
+//! A search starts from a snapshot of every address, each filter compares the current values
+//! against the ones from the last filter and keeps the candidates that pass. Hits can be pinned
+//! to a watch list that reads its values live.
+
+use std::fmt;
+
+use crate::mem::map::Memory;
+
+/// Searched address ranges: cartridge RAM, WRAM (the mapped banks) and HRAM
+const REGIONS: [(u16, u16); 3] = [(0xA000, 0xBFFF), (0xC000, 0xDFFF), (0xFF80, 0xFFFE)];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Size {
+    Byte,
+    /// Little endian, like the CPU's 16-bit loads and stores
+    Word,
+}
+
+impl Size {
+    fn len(self) -> u16 {
+        match self {
+            Size::Byte => 1,
+            Size::Word => 2,
+        }
+    }
+
+    pub fn read(self, mem: &Memory, addr: u16) -> u16 {
+        let byte = |addr: u16| mem.dma_read(addr as usize).unwrap_or(0xFF) as u16;
+        match self {
+            Size::Byte => byte(addr),
+            Size::Word => byte(addr) | byte(addr.wrapping_add(1)) << 8,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filter {
+    Equal,
+    Changed,
+    Increased,
+    Decreased,
+    Value(u16),
+}
+
+impl Filter {
+    fn keeps(self, previous: u16, current: u16) -> bool {
+        match self {
+            Filter::Equal => current == previous,
+            Filter::Changed => current != previous,
+            Filter::Increased => current > previous,
+            Filter::Decreased => current < previous,
+            Filter::Value(value) => current == value,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Candidate {
+    pub addr: u16,
+    /// The value at the last filter
+    pub value: u16,
+}
+
+pub struct RamSearch {
+    pub size: Size,
+    candidates: Vec<Candidate>,
+}
+
+impl RamSearch {
+    /// Snapshots every searchable address
+    pub fn new(mem: &Memory, size: Size) -> Self {
+        let candidates = REGIONS
+            .iter()
+            .flat_map(|&(start, end)| start..=end + 1 - size.len())
+            .map(|addr| Candidate {
+                addr,
+                value: size.read(mem, addr),
+            })
+            .collect();
+        Self { size, candidates }
+    }
+
+    /// Drops the candidates that don't pass `filter`, returns how many are left
+    pub fn filter(&mut self, mem: &Memory, filter: Filter) -> usize {
+        let size = self.size;
+        self.candidates.retain_mut(|candidate| {
+            let current = size.read(mem, candidate.addr);
+            let keep = filter.keeps(candidate.value, current);
+            candidate.value = current;
+            keep
+        });
+        self.candidates.len()
+    }
+
+    pub fn candidates(&self) -> &[Candidate] {
+        &self.candidates
+    }
+}
+
+/// A pinned address whose value is shown live
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Watch {
+    pub addr: u16,
+    pub size: Size,
+    pub label: String,
+}
+
+impl Watch {
+    pub fn value(&self, mem: &Memory) -> u16 {
+        self.size.read(mem, self.addr)
+    }
+
+    /// `label=value` with the current value, in decimal since that's what games show
+    pub fn show(&self, mem: &Memory) -> String {
+        format!("{}={}", self, self.value(mem))
+    }
+}
+
+impl fmt::Display for Watch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.label.is_empty() {
+            write!(f, "{:04X}", self.addr)
+        } else {
+            write!(f, "{}", self.label)
+        }
+    }
+}
+
+#[test]
+fn ram_search_test() {
+    use crate::gameboy::GameBoy;
+    let mut mem = Memory::init_rom(GameBoy::test_rom(&[]), GameBoy::test_header());
+    let mut search = RamSearch::new(&mem, Size::Byte);
+    mem.dma_write(0xC123, 3).unwrap();
+    mem.dma_write(0xFF90, 7).unwrap();
+    assert_eq!(search.filter(&mem, Filter::Changed), 2);
+    mem.dma_write(0xC123, 2).unwrap();
+    assert_eq!(search.filter(&mem, Filter::Decreased), 1);
+    assert_eq!(search.filter(&mem, Filter::Value(2)), 1);
+    assert_eq!(search.candidates()[0].addr, 0xC123);
+
+    let mut search = RamSearch::new(&mem, Size::Word);
+    mem.dma_write(0xC200, 0x34).unwrap();
+    mem.dma_write(0xC201, 0x12).unwrap();
+    search.filter(&mem, Filter::Increased);
+    assert_eq!(search.filter(&mem, Filter::Value(0x1234)), 1);
+    assert_eq!(search.candidates()[0].addr, 0xC200);
+    assert!(search.candidates().iter().all(|c| c.addr != 0xFFFE));
+
+    let watch = Watch {
+        addr: 0xC200,
+        size: Size::Word,
+        label: "hp".to_string(),
+    };
+    assert_eq!(watch.show(&mem), "hp=4660");
+}
@@ -0,0 +1,389 @@
+//! Audio/video recording of every emulated frame
+//!
+//! Two outputs are supported:
+//! - an uncompressed AVI (RGB24 video, 16-bit stereo PCM audio)
+//! - a directory of numbered PNG frames next to a WAV file, for editors that choke on big AVIs
+//!
+//! Video runs at exactly 4194304 / 70224 (~59.7275) frames per second, the DMG refresh rate,
+//! so audio and video stay in sync over long recordings. Plain AVI tops out at 1 GiB, about four
+//! minutes of footage, recording stops with an error once that's reached.
+
+use std::{
+    fs::File,
+    io::{BufWriter, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    error::GBError,
+    gameboy::GameBoy,
+    screenshot::{self, HEIGHT, WIDTH},
+};
+
+/// Frame rate as a fraction, T-cycles per second over T-cycles per frame
+pub const FPS_NUM: u32 = 4194304;
+pub const FPS_DEN: u32 = 70224;
+pub const SAMPLE_RATE: u32 = 44100;
+const CHANNELS: u16 = 2;
+const BLOCK_ALIGN: u16 = CHANNELS * 2;
+const FRAME_SIZE: u32 = WIDTH * HEIGHT * 3;
+// Everything before the first 'movi' chunk, rewritten with the final sizes when done
+const AVI_HEADER_SIZE: u64 = 324;
+const AVI_MAX_SIZE: u64 = 1 << 30;
+const WAV_HEADER_SIZE: u64 = 44;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    #[default]
+    Avi,
+    PngWav,
+}
+
+enum Output {
+    Avi {
+        file: BufWriter<File>,
+        /// idx1 entries, (chunk id, offset from 'movi', size)
+        index: Vec<([u8; 4], u32, u32)>,
+        movi_size: u64,
+    },
+    PngWav {
+        dir: PathBuf,
+        wav: BufWriter<File>,
+    },
+}
+
+pub struct Recorder {
+    output: Output,
+    frames: u32,
+    /// Stereo sample pairs written so far
+    samples: u32,
+}
+
+pub fn default_dir() -> PathBuf {
+    dirs::video_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("RedGB")
+}
+
+/// A new timestamped recording path in `dir`, `<title>_<time>.avi` or a `<title>_<time>` directory
+pub fn new_path(dir: &Path, title: &str, format: Format) -> PathBuf {
+    let title = title.trim_end_matches('\0').trim();
+    let name = format!(
+        "{}_{}",
+        if title.is_empty() { "redgb" } else { title },
+        screenshot::timestamp()
+    );
+    match format {
+        Format::Avi => dir.join(name + ".avi"),
+        Format::PngWav => dir.join(name),
+    }
+}
+
+impl Recorder {
+    /// Starts recording to `path`, an AVI file or a directory for the PNG frames and `audio.wav`
+    pub fn create(path: &Path, format: Format) -> Result<Self, GBError> {
+        let output = match format {
+            Format::Avi => {
+                if let Some(dir) = path.parent() {
+                    std::fs::create_dir_all(dir).map_err(record_error)?;
+                }
+                let mut file = BufWriter::new(File::create(path).map_err(record_error)?);
+                file.write_all(&avi_header(0, 0, 0)).map_err(record_error)?;
+                Output::Avi {
+                    file,
+                    index: vec![],
+                    movi_size: 0,
+                }
+            }
+            Format::PngWav => {
+                std::fs::create_dir_all(path).map_err(record_error)?;
+                let mut wav =
+                    BufWriter::new(File::create(path.join("audio.wav")).map_err(record_error)?);
+                wav.write_all(&wav_header(0)).map_err(record_error)?;
+                Output::PngWav {
+                    dir: path.to_path_buf(),
+                    wav,
+                }
+            }
+        };
+        Ok(Self {
+            output,
+            frames: 0,
+            samples: 0,
+        })
+    }
+
+    /// Records the frame `gameboy` just finished, if it did, and the samples it produced since
+    /// the last call. Has to run before the frontend drains the audio
+    pub fn capture(&mut self, gameboy: &GameBoy) -> Result<(), GBError> {
+        if gameboy.bus.ppu.frame_flag {
+            self.push_frame(gameboy.framebuffer())?;
+        }
+        self.push_audio(&gameboy.bus.apu.samples)
+    }
+
+    /// Adds a 160x144 RGB24 frame
+    pub fn push_frame(&mut self, rgb: &[u8]) -> Result<(), GBError> {
+        match &mut self.output {
+            Output::Avi {
+                file,
+                index,
+                movi_size,
+            } => {
+                // DIBs are stored bottom-up in BGR order
+                let mut dib = Vec::with_capacity(FRAME_SIZE as usize);
+                for row in rgb.chunks(WIDTH as usize * 3).rev() {
+                    for pixel in row.chunks(3) {
+                        dib.extend_from_slice(&[pixel[2], pixel[1], pixel[0]]);
+                    }
+                }
+                write_movi_chunk(file, index, movi_size, *b"00db", &dib)?;
+            }
+            Output::PngWav { dir, .. } => {
+                let path = dir.join(format!("frame_{:06}.png", self.frames));
+                let file = File::create(path).map_err(record_error)?;
+                screenshot::write_png(BufWriter::new(file), rgb, WIDTH, HEIGHT, 1)?;
+            }
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// Adds interleaved left/right samples at 44.1 KHz
+    pub fn push_audio(&mut self, samples: &[f32]) -> Result<(), GBError> {
+        if samples.is_empty() {
+            return Ok(());
+        }
+        let pcm: Vec<u8> = samples
+            .iter()
+            .flat_map(|sample| ((sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16).to_le_bytes())
+            .collect();
+        match &mut self.output {
+            Output::Avi {
+                file,
+                index,
+                movi_size,
+            } => write_movi_chunk(file, index, movi_size, *b"01wb", &pcm)?,
+            Output::PngWav { wav, .. } => wav.write_all(&pcm).map_err(record_error)?,
+        }
+        self.samples += (samples.len() / CHANNELS as usize) as u32;
+        Ok(())
+    }
+
+    pub fn frames(&self) -> u32 {
+        self.frames
+    }
+
+    /// Writes the index and final sizes, the output isn't playable before this
+    pub fn finish(self) -> Result<(), GBError> {
+        match self.output {
+            Output::Avi {
+                mut file,
+                index,
+                movi_size,
+            } => {
+                let mut idx1 = Vec::with_capacity(index.len() * 16);
+                for (id, offset, size) in index {
+                    idx1.extend_from_slice(&id);
+                    // AVIIF_KEYFRAME, every chunk stands on its own
+                    idx1.extend_from_slice(&0x10u32.to_le_bytes());
+                    idx1.extend_from_slice(&offset.to_le_bytes());
+                    idx1.extend_from_slice(&size.to_le_bytes());
+                }
+                let mut idx1_chunk = vec![];
+                chunk(&mut idx1_chunk, *b"idx1", &idx1);
+                file.write_all(&idx1_chunk).map_err(record_error)?;
+                let riff_size = AVI_HEADER_SIZE - 8 + movi_size + idx1_chunk.len() as u64;
+                let mut header = avi_header(self.frames, self.samples, movi_size);
+                header[4..8].copy_from_slice(&(riff_size as u32).to_le_bytes());
+                file.seek(SeekFrom::Start(0)).map_err(record_error)?;
+                file.write_all(&header).map_err(record_error)?;
+                file.flush().map_err(record_error)
+            }
+            Output::PngWav { mut wav, .. } => {
+                wav.seek(SeekFrom::Start(0)).map_err(record_error)?;
+                wav.write_all(&wav_header(self.samples))
+                    .map_err(record_error)?;
+                wav.flush().map_err(record_error)
+            }
+        }
+    }
+}
+
+fn record_error(e: impl ToString) -> GBError {
+    GBError::RecordError(e.to_string())
+}
+
+fn write_movi_chunk(
+    file: &mut BufWriter<File>,
+    index: &mut Vec<([u8; 4], u32, u32)>,
+    movi_size: &mut u64,
+    id: [u8; 4],
+    data: &[u8],
+) -> Result<(), GBError> {
+    if AVI_HEADER_SIZE + *movi_size + 8 + data.len() as u64 + 16 * (index.len() as u64 + 1)
+        > AVI_MAX_SIZE
+    {
+        return Err(GBError::RecordError("AVI size limit reached".to_string()));
+    }
+    let mut out = Vec::with_capacity(data.len() + 8);
+    chunk(&mut out, id, data);
+    file.write_all(&out).map_err(record_error)?;
+    // Offsets count from the 'movi' fourcc
+    index.push((id, 4 + *movi_size as u32, data.len() as u32));
+    *movi_size += out.len() as u64;
+    Ok(())
+}
+
+fn chunk(out: &mut Vec<u8>, id: [u8; 4], data: &[u8]) {
+    out.extend_from_slice(&id);
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out.extend_from_slice(data);
+    if data.len() % 2 == 1 {
+        out.push(0);
+    }
+}
+
+fn list(out: &mut Vec<u8>, kind: [u8; 4], data: &[u8]) {
+    let mut body = kind.to_vec();
+    body.extend_from_slice(data);
+    chunk(out, *b"LIST", &body);
+}
+
+fn u16s(values: &[u16]) -> Vec<u8> {
+    values
+        .iter()
+        .flat_map(|value| value.to_le_bytes())
+        .collect()
+}
+
+fn u32s(values: &[u32]) -> Vec<u8> {
+    values
+        .iter()
+        .flat_map(|value| value.to_le_bytes())
+        .collect()
+}
+
+/// RIFF header, hdrl list and the start of the movi list. The RIFF size is left at 0
+fn avi_header(frames: u32, samples: u32, movi_size: u64) -> Vec<u8> {
+    let byte_rate = SAMPLE_RATE * BLOCK_ALIGN as u32;
+    let micros_per_frame = (1_000_000u64 * FPS_DEN as u64 / FPS_NUM as u64) as u32;
+    let max_bytes_per_sec = FRAME_SIZE * 60 + byte_rate;
+    let mut avih = u32s(&[
+        micros_per_frame,
+        max_bytes_per_sec,
+        0,
+        // AVIF_HASINDEX | AVIF_ISINTERLEAVED
+        0x10 | 0x100,
+        frames,
+        0,
+        2,
+        FRAME_SIZE,
+        WIDTH,
+        HEIGHT,
+    ]);
+    avih.extend_from_slice(&[0; 16]);
+
+    let mut video_strh = b"vidsDIB ".to_vec();
+    video_strh.extend(u32s(&[
+        0,
+        0,
+        0,
+        FPS_DEN,
+        FPS_NUM,
+        0,
+        frames,
+        FRAME_SIZE,
+        u32::MAX,
+        0,
+    ]));
+    video_strh.extend(u16s(&[0, 0, WIDTH as u16, HEIGHT as u16]));
+    let mut video_strf = u32s(&[40, WIDTH, HEIGHT]);
+    video_strf.extend(u16s(&[1, 24]));
+    video_strf.extend(u32s(&[0, FRAME_SIZE, 0, 0, 0, 0]));
+    let mut video_strl = vec![];
+    chunk(&mut video_strl, *b"strh", &video_strh);
+    chunk(&mut video_strl, *b"strf", &video_strf);
+
+    let mut audio_strh = b"auds".to_vec();
+    audio_strh.extend(u32s(&[
+        0,
+        0,
+        0,
+        0,
+        BLOCK_ALIGN as u32,
+        byte_rate,
+        0,
+        samples,
+        byte_rate,
+        u32::MAX,
+        BLOCK_ALIGN as u32,
+    ]));
+    audio_strh.extend(u16s(&[0, 0, 0, 0]));
+    let mut audio_strl = vec![];
+    chunk(&mut audio_strl, *b"strh", &audio_strh);
+    chunk(&mut audio_strl, *b"strf", &wave_format());
+
+    let mut hdrl = vec![];
+    chunk(&mut hdrl, *b"avih", &avih);
+    list(&mut hdrl, *b"strl", &video_strl);
+    list(&mut hdrl, *b"strl", &audio_strl);
+
+    let mut header = b"RIFF".to_vec();
+    header.extend_from_slice(&0u32.to_le_bytes());
+    header.extend_from_slice(b"AVI ");
+    list(&mut header, *b"hdrl", &hdrl);
+    header.extend_from_slice(b"LIST");
+    header.extend_from_slice(&(4 + movi_size as u32).to_le_bytes());
+    header.extend_from_slice(b"movi");
+    debug_assert_eq!(header.len() as u64, AVI_HEADER_SIZE);
+    header
+}
+
+/// PCMWAVEFORMAT, 16-bit stereo
+fn wave_format() -> Vec<u8> {
+    let mut format = u16s(&[1, CHANNELS]);
+    format.extend(u32s(&[SAMPLE_RATE, SAMPLE_RATE * BLOCK_ALIGN as u32]));
+    format.extend(u16s(&[BLOCK_ALIGN, 16]));
+    format
+}
+
+fn wav_header(samples: u32) -> Vec<u8> {
+    let data_size = samples * BLOCK_ALIGN as u32;
+    let mut header = b"RIFF".to_vec();
+    header.extend_from_slice(&(WAV_HEADER_SIZE as u32 - 8 + data_size).to_le_bytes());
+    header.extend_from_slice(b"WAVE");
+    chunk(&mut header, *b"fmt ", &wave_format());
+    header.extend_from_slice(b"data");
+    header.extend_from_slice(&data_size.to_le_bytes());
+    header
+}
+
+#[test]
+fn avi_test() {
+    let path = std::env::temp_dir().join(format!("redgb_avi_test_{}.avi", std::process::id()));
+    let mut recorder = Recorder::create(&path, Format::Avi).unwrap();
+    let mut frame = vec![0; FRAME_SIZE as usize];
+    // Top left pixel, ends up at the start of the last DIB row
+    frame[..3].copy_from_slice(&[1, 2, 3]);
+    for _ in 0..3 {
+        recorder.push_frame(&frame).unwrap();
+        recorder.push_audio(&[0.5; 1470]).unwrap();
+    }
+    recorder.finish().unwrap();
+    let avi = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).ok();
+
+    let u32_at = |offset: usize| u32::from_le_bytes(avi[offset..offset + 4].try_into().unwrap());
+    assert_eq!(&avi[..4], b"RIFF");
+    assert_eq!(u32_at(4) as usize, avi.len() - 8);
+    // avih total frames
+    assert_eq!(u32_at(48), 3);
+    let movi = AVI_HEADER_SIZE as usize;
+    assert_eq!(&avi[movi..movi + 4], b"00db");
+    let last_row = movi + 8 + (FRAME_SIZE - WIDTH * 3) as usize;
+    assert_eq!(avi[last_row..last_row + 3], [3, 2, 1]);
+    let idx1 = avi.len() - 8 - 6 * 16;
+    assert_eq!(&avi[idx1..idx1 + 4], b"idx1");
+    assert_eq!(wav_header(735).len() as u64, WAV_HEADER_SIZE);
+}
@@ -0,0 +1,192 @@
+//! Rewind history built from [`state`] snapshots
+//!
+//! Only the newest snapshot is kept as is, every older one is stored as the XOR against its
+//! successor, run length encoded. Consecutive frames differ in a few hundred bytes, so a snapshot
+//! ends up a tiny fraction of a full save state.
+//!
+//! [`state`]: crate::state
+
+use std::collections::VecDeque;
+
+use crate::{error::GBError, gameboy::GameBoy};
+
+pub const DEFAULT_INTERVAL: u32 = 2;
+pub const DEFAULT_CAPACITY: usize = 1800;
+
+pub struct Rewind {
+    /// Frames between snapshots
+    interval: u32,
+    /// Maximum number of snapshots, the oldest ones are dropped first
+    capacity: usize,
+    frames: u32,
+    newest: Option<Vec<u8>>,
+    /// Compressed deltas, the back is the one right before `newest`
+    older: VecDeque<Vec<u8>>,
+}
+
+impl Default for Rewind {
+    fn default() -> Self {
+        Self::new(DEFAULT_INTERVAL, DEFAULT_CAPACITY)
+    }
+}
+
+impl Rewind {
+    pub fn new(interval: u32, capacity: usize) -> Self {
+        Self {
+            interval: interval.max(1),
+            capacity: capacity.max(1),
+            frames: 0,
+            newest: None,
+            older: VecDeque::new(),
+        }
+    }
+
+    /// Call once per emulated frame, snapshots every `interval` frames
+    pub fn record(&mut self, gameboy: &GameBoy) {
+        self.frames += 1;
+        if self.frames < self.interval {
+            return;
+        }
+        self.frames = 0;
+        let snapshot = gameboy.save_state();
+        if let Some(newest) = self.newest.replace(snapshot) {
+            let delta = compress(&newest, self.newest.as_ref().unwrap());
+            self.older.push_back(delta);
+            if self.older.len() >= self.capacity {
+                self.older.pop_front();
+            }
+        }
+    }
+
+    /// Restores the newest snapshot and drops it, so repeated calls walk back through time.
+    /// The oldest snapshot is never dropped, returns false once it's been reached
+    pub fn step_back(&mut self, gameboy: &mut GameBoy) -> Result<bool, GBError> {
+        let Some(newest) = self.newest.as_mut() else {
+            return Ok(false);
+        };
+        gameboy.load_state(newest)?;
+        self.frames = 0;
+        match self.older.pop_back() {
+            Some(delta) => {
+                *newest = decompress(&delta, newest)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Call once per displayed frame while rewinding, steps back every `interval` frames so
+    /// the history plays back at the speed it was recorded
+    pub fn rewind_frame(&mut self, gameboy: &mut GameBoy) -> Result<bool, GBError> {
+        self.frames += 1;
+        if self.frames < self.interval {
+            return Ok(!self.older.is_empty());
+        }
+        self.step_back(gameboy)
+    }
+
+    /// Number of snapshots that can be stepped back through
+    pub fn len(&self) -> usize {
+        self.older.len() + self.newest.is_some() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.newest.is_none()
+    }
+
+    pub fn clear(&mut self) {
+        self.frames = 0;
+        self.newest = None;
+        self.older.clear();
+    }
+}
+
+// Delta layout: length of `old`: u32 | (zero run: u16 | literal count: u16 | literals)*
+// where the bytes are `old` XOR `new`, `new` being treated as zero padded
+fn compress(old: &[u8], new: &[u8]) -> Vec<u8> {
+    let xor: Vec<u8> = old
+        .iter()
+        .enumerate()
+        .map(|(i, byte)| byte ^ new.get(i).copied().unwrap_or(0))
+        .collect();
+    let mut out = (old.len() as u32).to_le_bytes().to_vec();
+    let mut pos = 0;
+    while pos < xor.len() {
+        let zeros = xor[pos..]
+            .iter()
+            .take(u16::MAX as usize)
+            .take_while(|&&byte| byte == 0)
+            .count();
+        pos += zeros;
+        let literals = xor[pos..]
+            .iter()
+            .take(u16::MAX as usize)
+            .take_while(|&&byte| byte != 0)
+            .count();
+        out.extend_from_slice(&(zeros as u16).to_le_bytes());
+        out.extend_from_slice(&(literals as u16).to_le_bytes());
+        out.extend_from_slice(&xor[pos..pos + literals]);
+        pos += literals;
+    }
+    out
+}
+
+fn decompress(delta: &[u8], new: &[u8]) -> Result<Vec<u8>, GBError> {
+    let len_bytes = delta.get(..4).ok_or(GBError::InvalidState)?;
+    let len = u32::from_le_bytes(len_bytes.try_into().unwrap()) as usize;
+    let mut old: Vec<u8> = (0..len).map(|i| new.get(i).copied().unwrap_or(0)).collect();
+    let mut pos = 0;
+    let mut input = &delta[4..];
+    while !input.is_empty() {
+        let header = input.get(..4).ok_or(GBError::InvalidState)?;
+        let zeros = u16::from_le_bytes([header[0], header[1]]) as usize;
+        let literals = u16::from_le_bytes([header[2], header[3]]) as usize;
+        pos += zeros;
+        let bytes = input.get(4..4 + literals).ok_or(GBError::InvalidState)?;
+        let target = old
+            .get_mut(pos..pos + literals)
+            .ok_or(GBError::InvalidState)?;
+        for (byte, xor) in target.iter_mut().zip(bytes) {
+            *byte ^= xor;
+        }
+        pos += literals;
+        input = &input[4 + literals..];
+    }
+    Ok(old)
+}
+
+#[test]
+fn rewind_test() {
+    // inc a, ld [0xC000], a, jr -6
+    let mut gameboy = GameBoy::test_with_code(&[0x3C, 0xEA, 0x00, 0xC0, 0x18, 0xF9]);
+    let mut rewind = Rewind::new(1, 3);
+    let mut snapshots = vec![];
+    for _ in 0..5 {
+        gameboy.run_frame().unwrap();
+        rewind.record(&gameboy);
+        snapshots.push(gameboy.save_state());
+    }
+    assert_eq!(rewind.len(), 3);
+    assert!(compress(&snapshots[3], &snapshots[4]).len() < snapshots[3].len() / 10);
+
+    for expected in snapshots[2..].iter().rev() {
+        rewind.step_back(&mut gameboy).unwrap();
+        assert_eq!(&gameboy.save_state(), expected);
+    }
+    assert!(!rewind.step_back(&mut gameboy).unwrap());
+    assert_eq!(gameboy.save_state(), snapshots[2]);
+
+    // Snapshots every 2 frames are played back every 2 frames
+    let mut rewind = Rewind::new(2, 3);
+    for _ in 0..4 {
+        gameboy.run_frame().unwrap();
+        rewind.record(&gameboy);
+    }
+    let newest = gameboy.save_state();
+    gameboy.run_frame().unwrap();
+    let current = gameboy.save_state();
+    rewind.rewind_frame(&mut gameboy).unwrap();
+    assert_eq!(gameboy.save_state(), current);
+    rewind.rewind_frame(&mut gameboy).unwrap();
+    assert_eq!(gameboy.save_state(), newest);
+}
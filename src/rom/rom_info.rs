@@ -1,13 +1,10 @@
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub enum CGBMode {
+    #[default]
     Monochrome,
-    Color { exclusive: bool },
-}
-
-impl Default for CGBMode {
-    fn default() -> Self {
-        Self::Color { exclusive: false }
-    }
+    Color {
+        exclusive: bool,
+    },
 }
 
 #[derive(Debug)]
@@ -20,6 +17,8 @@ pub struct ROMInfo {
     pub mem_banks: u16,
     pub header_checksum: u8,
     pub rom_checksum: u16,
+    /// Appended to the title for the save file name, keeps a second copy of the same game apart
+    pub save_suffix: String,
 }
 
 impl Default for ROMInfo {
@@ -33,6 +32,7 @@ impl Default for ROMInfo {
             mem_banks: 0x3,
             header_checksum: u8::default(),
             rom_checksum: u16::default(),
+            save_suffix: String::default(),
         }
     }
 }
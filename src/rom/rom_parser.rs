@@ -22,7 +22,7 @@ const HEADER_CHECKSUM_ADDR: usize = 0x14D;
 const ROM_CHECKSUM_RANGE: RangeInclusive<usize> = 0x14E..=0x14F;
 
 /// Extracts important ROM data from ROM header and preforms validation
-pub fn parse_rom_header(rom: &Vec<u8>) -> ROMInfo {
+pub fn parse_rom_header(rom: &[u8]) -> ROMInfo {
     assert!(rom.len() > HEADER_SIZE, "Invalid ROM File (File too short)");
     assert!(
         validate_rom(rom),
@@ -82,6 +82,7 @@ pub fn parse_rom_header(rom: &Vec<u8>) -> ROMInfo {
         mem_banks,
         header_checksum,
         rom_checksum,
+        save_suffix: String::new(),
     };
     println!("{:#?}", info);
     info
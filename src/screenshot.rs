@@ -0,0 +1,130 @@
+//! PNG screenshots of the framebuffer
+//!
+//! Files are named `<title>_<UTC date>_<time>.png` so they sort chronologically, by default they
+//! go to `RedGB` in the pictures dir.
+
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::error::GBError;
+
+pub const WIDTH: u32 = 160;
+pub const HEIGHT: u32 = 144;
+
+pub fn default_dir() -> PathBuf {
+    dirs::picture_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("RedGB")
+}
+
+/// Writes `rgb` (RGB24, `width` x `height`) as a PNG, every pixel blown up to `scale` x `scale`
+pub fn write_png(
+    out: impl Write,
+    rgb: &[u8],
+    width: u32,
+    height: u32,
+    scale: u32,
+) -> Result<(), GBError> {
+    let scale = scale.max(1);
+    let row_len = (width * 3) as usize;
+    if rgb.len() != row_len * height as usize {
+        return Err(GBError::ImageError(format!(
+            "{} bytes of pixels for a {width}x{height} image",
+            rgb.len()
+        )));
+    }
+    let mut encoder = png::Encoder::new(out, width * scale, height * scale);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder
+        .write_header()
+        .map_err(|e| GBError::ImageError(e.to_string()))?;
+    let mut scaled = Vec::with_capacity(rgb.len() * (scale * scale) as usize);
+    for row in rgb.chunks(row_len) {
+        let start = scaled.len();
+        for pixel in row.chunks(3) {
+            for _ in 0..scale {
+                scaled.extend_from_slice(pixel);
+            }
+        }
+        for _ in 1..scale {
+            scaled.extend_from_within(start..);
+        }
+    }
+    writer
+        .write_image_data(&scaled)
+        .and_then(|_| writer.finish())
+        .map_err(|e| GBError::ImageError(e.to_string()))
+}
+
+/// Saves a 160x144 framebuffer to a new timestamped file in `dir`, returning its path
+pub fn save(framebuffer: &[u8], dir: &Path, title: &str, scale: u32) -> Result<PathBuf, GBError> {
+    std::fs::create_dir_all(dir).map_err(|e| GBError::ImageError(e.to_string()))?;
+    let title = title.trim_end_matches('\0').trim();
+    let path = dir.join(format!(
+        "{}_{}.png",
+        if title.is_empty() { "redgb" } else { title },
+        timestamp()
+    ));
+    let file = File::create(&path).map_err(|e| GBError::ImageError(e.to_string()))?;
+    write_png(BufWriter::new(file), framebuffer, WIDTH, HEIGHT, scale)?;
+    Ok(path)
+}
+
+/// Current UTC time as `YYYY-MM-DD_HH-MM-SS.mmm`
+pub fn timestamp() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let secs = now.as_secs();
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    format!(
+        "{year:04}-{month:02}-{day:02}_{:02}-{:02}-{:02}.{:03}",
+        secs / 3600 % 24,
+        secs / 60 % 60,
+        secs % 60,
+        now.subsec_millis()
+    )
+}
+
+// https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    (year, month, day)
+}
+
+#[test]
+fn write_png_test() {
+    assert_eq!(civil_from_days(0), (1970, 1, 1));
+    assert_eq!(civil_from_days(20744), (2026, 10, 18));
+
+    let mut rgb = vec![0; (WIDTH * HEIGHT * 3) as usize];
+    rgb[3..6].copy_from_slice(&[1, 2, 3]);
+    let mut png = vec![];
+    write_png(&mut png, &rgb, WIDTH, HEIGHT, 2).unwrap();
+    let mut reader = png::Decoder::new(std::io::Cursor::new(png))
+        .read_info()
+        .unwrap();
+    let mut decoded = vec![0; reader.output_buffer_size().unwrap()];
+    let info = reader.next_frame(&mut decoded).unwrap();
+    assert_eq!((info.width, info.height), (WIDTH * 2, HEIGHT * 2));
+    let row = (WIDTH * 2 * 3) as usize;
+    // Pixel 1 covers pixels 2 and 3 of the first two rows
+    for offset in [6, 9, row + 6, row + 9] {
+        assert_eq!(decoded[offset..offset + 3], [1, 2, 3]);
+    }
+    assert_eq!(decoded[12..15], [0, 0, 0]);
+    assert!(write_png(vec![], &rgb[1..], WIDTH, HEIGHT, 1).is_err());
+}
@@ -0,0 +1,163 @@
+//! Link cable between two emulator instances over TCP
+//!
+//! Whichever side starts a transfer with the internal clock is the master for that byte: it sends
+//! `TRANSFER, byte` and blocks until the other side answers `REPLY, byte`. The other side answers
+//! on its next poll, with its SB (completing its transfer) if it was waiting on the external
+//! clock, or 0xFF if it wasn't, just like an unconnected cable.
+//!
+//! A master that gets no reply within [`TIMEOUT`] reads 0xFF, the late reply is dropped.
+
+use std::{
+    io::{self, ErrorKind, Read, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    time::{Duration, Instant},
+};
+
+use log::{info, warn};
+
+use crate::serial::SerialDevice;
+
+const TRANSFER: u8 = 0x01;
+const REPLY: u8 = 0x02;
+/// How long a master waits for the other side before giving up on a byte
+pub const TIMEOUT: Duration = Duration::from_millis(500);
+
+pub struct TcpLink {
+    stream: TcpStream,
+    /// Received bytes that don't make up a whole message yet
+    pending: Vec<u8>,
+    /// Replies to transfers that already timed out
+    stale_replies: u32,
+    connected: bool,
+}
+
+impl TcpLink {
+    /// Waits for the other instance to connect on `addr`
+    pub fn host(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        info!("Waiting for the link cable on {}", listener.local_addr()?);
+        let (stream, peer) = listener.accept()?;
+        info!("Link cable connected to {peer}");
+        Self::new(stream)
+    }
+
+    pub fn connect(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let stream = TcpStream::connect(addr)?;
+        info!("Link cable connected to {}", stream.peer_addr()?);
+        Self::new(stream)
+    }
+
+    fn new(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nodelay(true)?;
+        stream.set_nonblocking(true)?;
+        Ok(Self {
+            stream,
+            pending: vec![],
+            stale_replies: 0,
+            connected: true,
+        })
+    }
+
+    fn send(&mut self, kind: u8, byte: u8) {
+        if !self.connected {
+            return;
+        }
+        // Two bytes always fit in the socket buffer, a short write means the peer is gone
+        if let Err(e) = self.stream.write_all(&[kind, byte]) {
+            self.disconnect(e);
+        }
+    }
+
+    /// Next whole message, if one has arrived
+    fn receive(&mut self) -> Option<(u8, u8)> {
+        if self.pending.len() < 2 && self.connected {
+            let mut buf = [0; 64];
+            match self.stream.read(&mut buf) {
+                Ok(0) => self.disconnect(io::Error::from(ErrorKind::UnexpectedEof)),
+                Ok(len) => self.pending.extend_from_slice(&buf[..len]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => (),
+                Err(e) => self.disconnect(e),
+            }
+        }
+        if self.pending.len() < 2 {
+            return None;
+        }
+        let message = (self.pending[0], self.pending[1]);
+        self.pending.drain(..2);
+        Some(message)
+    }
+
+    fn disconnect(&mut self, e: io::Error) {
+        warn!("Link cable disconnected: {e}");
+        self.connected = false;
+    }
+
+    /// Handles a message outside of our own transfer, returns the byte of a transfer the other
+    /// side clocked
+    fn handle(&mut self, (kind, byte): (u8, u8), waiting: Option<u8>) -> Option<u8> {
+        match kind {
+            TRANSFER => {
+                self.send(REPLY, waiting.unwrap_or(0xFF));
+                Some(byte)
+            }
+            REPLY if self.stale_replies > 0 => {
+                self.stale_replies -= 1;
+                None
+            }
+            _ => {
+                warn!("Unexpected link cable message {kind:#04X}");
+                None
+            }
+        }
+    }
+}
+
+impl SerialDevice for TcpLink {
+    fn transfer(&mut self, byte: u8) -> u8 {
+        if !self.connected {
+            return 0xFF;
+        }
+        self.send(TRANSFER, byte);
+        let deadline = Instant::now() + TIMEOUT;
+        while self.connected && Instant::now() < deadline {
+            match self.receive() {
+                Some((REPLY, _)) if self.stale_replies > 0 => self.stale_replies -= 1,
+                Some((REPLY, reply)) => return reply,
+                // Both sides started a transfer at once, neither is listening to the other's
+                // clock so both read 0xFF
+                Some(message) => {
+                    self.handle(message, None);
+                }
+                None => std::thread::sleep(Duration::from_micros(50)),
+            }
+        }
+        self.stale_replies += 1;
+        0xFF
+    }
+
+    fn poll(&mut self, waiting: Option<u8>) -> Option<u8> {
+        let message = self.receive()?;
+        self.handle(message, waiting)
+    }
+}
+
+#[test]
+fn link_test() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let master = std::thread::spawn(move || {
+        let mut link = TcpLink::connect(addr).unwrap();
+        [link.transfer(0x42), link.transfer(0x43)]
+    });
+    let mut slave = TcpLink::new(listener.accept().unwrap().0).unwrap();
+    let mut received = vec![];
+    // Not waiting for the first byte, so the master reads 0xFF
+    while received.is_empty() {
+        received.extend(slave.poll(None));
+    }
+    while received.len() < 2 {
+        received.extend(slave.poll(Some(0x99)));
+    }
+    assert_eq!(received, [0x42, 0x43]);
+    assert_eq!(master.join().unwrap(), [0xFF, 0x99]);
+}
@@ -0,0 +1,101 @@
+//! Serial port (SB/SC) and the devices that can be plugged into it
+//!
+//! With the internal clock (SC bit 0) the Game Boy is the master, a byte takes 4096 T-cycles
+//! (8 bits at 8192 Hz, 128 with the CGB fast clock) and is then exchanged with the device.
+//! With the external clock the Game Boy waits until the device clocks a byte in.
+//! Without a device, transfers read back 0xFF like with nothing plugged in.
+
+pub mod link;
+pub mod printer;
+
+use crate::{
+    cpu::alu,
+    error::GBError,
+    mem::map::Memory,
+    state::{SaveState, StateReader, StateWriter},
+};
+
+const SB: usize = 0x01;
+const SC: usize = 0x02;
+const IF: usize = 0x0F;
+/// T-cycles per byte at 8192 Hz
+const BYTE_CYCLES: u32 = 4096;
+/// T-cycles per byte with the CGB fast clock, 262144 Hz
+const FAST_BYTE_CYCLES: u32 = 128;
+/// T-cycles between device polls, one bit at 8192 Hz
+const POLL_CYCLES: u32 = 512;
+
+/// Anything on the other end of the link cable
+pub trait SerialDevice: Send {
+    /// The Game Boy clocked `byte` out with its internal clock, returns the byte shifted in
+    fn transfer(&mut self, byte: u8) -> u8;
+
+    /// Polled every 512 T-cycles unless the Game Boy is clocking a transfer itself. `waiting` is
+    /// SB while it waits on the external clock (SC = 0x80). Returns the byte the device clocked
+    /// in, which completes the transfer if the Game Boy was waiting
+    fn poll(&mut self, _waiting: Option<u8>) -> Option<u8> {
+        None
+    }
+}
+
+#[derive(Default)]
+pub struct Serial {
+    pub device: Option<Box<dyn SerialDevice>>,
+    /// T-cycles left in the current internal clock transfer
+    remaining: u32,
+    poll_cycles: u32,
+}
+
+impl Serial {
+    /// SC was written, a transfer starts when bit 7 is set
+    pub fn control_written(&mut self, mem: &Memory) {
+        let sc = mem.io[SC];
+        self.remaining = match sc & 0x83 {
+            0x83 if mem.cgb => FAST_BYTE_CYCLES,
+            0x81 | 0x83 => BYTE_CYCLES,
+            _ => 0,
+        };
+    }
+
+    pub fn tick(&mut self, mem: &mut Memory) {
+        let sc = mem.io[SC];
+        let transferring = alu::read_bits(sc, 7, 1) == 1;
+        let incoming = if transferring && alu::read_bits(sc, 0, 1) == 1 {
+            self.remaining = self.remaining.saturating_sub(4);
+            if self.remaining > 0 {
+                return;
+            }
+            match self.device.as_mut() {
+                Some(device) => device.transfer(mem.io[SB]),
+                None => 0xFF,
+            }
+        } else {
+            let Some(device) = self.device.as_mut() else {
+                return;
+            };
+            self.poll_cycles += 4;
+            if self.poll_cycles < POLL_CYCLES {
+                return;
+            }
+            self.poll_cycles = 0;
+            match device.poll(transferring.then_some(mem.io[SB])) {
+                Some(byte) if transferring => byte,
+                _ => return,
+            }
+        };
+        mem.io[SB] = incoming;
+        mem.io[SC] = alu::set_bit(sc, 7, false);
+        mem.io[IF] = alu::set_bit(mem.io[IF], 3, true);
+    }
+}
+
+// The device itself isn't part of the state, it has to be plugged in again after loading
+impl SaveState for Serial {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.u32(self.remaining);
+    }
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), GBError> {
+        self.remaining = r.u32()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,344 @@
+//! Game Boy Printer
+//!
+//! Packets are `88 33 | command | compression | length: u16 | data | checksum: u16 | 00 00`,
+//! the printer answers the two trailing zeroes with 0x81 (it's alive) and its status byte.
+//! Commands are 0x01 init, 0x02 print, 0x04 data and 0x0F status, data is either raw tiles or
+//! RLE compressed (`0x80 | n` repeats the next byte n + 2 times, `n` copies n + 1 literals).
+//!
+//! Printed images pile up on one sheet until a print command asks for a margin after the image,
+//! then the sheet is written to `dir` as `printout_<time>.png`.
+
+use std::{
+    fs::File,
+    io::BufWriter,
+    path::{Path, PathBuf},
+};
+
+use log::{error, info, warn};
+
+use crate::{error::GBError, screenshot, serial::SerialDevice};
+
+const INIT: u8 = 0x01;
+const PRINT: u8 = 0x02;
+const DATA: u8 = 0x04;
+const STATUS: u8 = 0x0F;
+
+// Status bits
+const CHECKSUM_ERROR: u8 = 0x01;
+const PRINTING: u8 = 0x02;
+const IMAGE_FULL: u8 = 0x04;
+const UNPROCESSED: u8 = 0x08;
+const PACKET_ERROR: u8 = 0x10;
+
+/// The printer holds at most 9 data packets, 160x144 pixels
+const BUFFER_SIZE: usize = 0x1680;
+const WIDTH: usize = 160;
+const TILES_PER_ROW: usize = WIDTH / 8;
+const SHADES: [u8; 4] = [0xFF, 0xAA, 0x55, 0x00];
+/// Status replies that still report printing after a print command
+const PRINT_POLLS: u8 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    Magic1,
+    Magic2,
+    Command,
+    Compression,
+    LengthLow,
+    LengthHigh,
+    Data,
+    ChecksumLow,
+    ChecksumHigh,
+    Alive,
+    Status,
+}
+
+pub struct Printer {
+    dir: PathBuf,
+    stage: Stage,
+    command: u8,
+    compressed: bool,
+    length: u16,
+    data: Vec<u8>,
+    checksum: u16,
+    received_checksum: u16,
+    status: u8,
+    print_polls: u8,
+    /// Decompressed tile data waiting for a print command
+    buffer: Vec<u8>,
+    /// RGB24 rows printed on the current sheet
+    sheet: Vec<u8>,
+    /// The last sheet written to disk
+    pub last_printout: Option<PathBuf>,
+}
+
+impl Printer {
+    /// A printer that saves its sheets to `dir`
+    pub fn new(dir: &Path) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+            stage: Stage::Magic1,
+            command: 0,
+            compressed: false,
+            length: 0,
+            data: vec![],
+            checksum: 0,
+            received_checksum: 0,
+            status: 0,
+            print_polls: 0,
+            buffer: Vec::with_capacity(BUFFER_SIZE),
+            sheet: vec![],
+            last_printout: None,
+        }
+    }
+
+    /// Writes out the current sheet, if anything has been printed on it
+    pub fn cut_sheet(&mut self) -> Result<Option<PathBuf>, GBError> {
+        if self.sheet.is_empty() {
+            return Ok(None);
+        }
+        std::fs::create_dir_all(&self.dir).map_err(|e| GBError::ImageError(e.to_string()))?;
+        let path = self
+            .dir
+            .join(format!("printout_{}.png", screenshot::timestamp()));
+        let file = File::create(&path).map_err(|e| GBError::ImageError(e.to_string()))?;
+        let height = (self.sheet.len() / (WIDTH * 3)) as u32;
+        let sheet = std::mem::take(&mut self.sheet);
+        screenshot::write_png(BufWriter::new(file), &sheet, WIDTH as u32, height, 1)?;
+        info!("Printed {}", path.display());
+        self.last_printout = Some(path.clone());
+        Ok(Some(path))
+    }
+
+    fn run_command(&mut self) {
+        self.status &= !(CHECKSUM_ERROR | PACKET_ERROR);
+        if self.checksum != self.received_checksum {
+            warn!("Printer packet checksum mismatch");
+            self.status |= CHECKSUM_ERROR;
+            return;
+        }
+        match self.command {
+            INIT => {
+                self.buffer.clear();
+                self.status = 0;
+                self.print_polls = 0;
+            }
+            DATA => {
+                let data = std::mem::take(&mut self.data);
+                if self.compressed {
+                    self.decompress(&data);
+                } else {
+                    self.append(&data);
+                }
+                self.data = data;
+                if !self.buffer.is_empty() {
+                    self.status |= UNPROCESSED;
+                }
+            }
+            PRINT if self.data.len() >= 4 => {
+                let (sheets, margins, palette) = (self.data[0], self.data[1], self.data[2]);
+                if sheets > 0 {
+                    self.print(palette);
+                }
+                self.buffer.clear();
+                self.status = (self.status & !(UNPROCESSED | IMAGE_FULL)) | PRINTING;
+                self.print_polls = PRINT_POLLS;
+                if margins & 0x0F != 0
+                    && let Err(e) = self.cut_sheet()
+                {
+                    error!("{}", e);
+                }
+            }
+            STATUS => (),
+            _ => self.status |= PACKET_ERROR,
+        }
+    }
+
+    fn append(&mut self, bytes: &[u8]) {
+        let room = BUFFER_SIZE - self.buffer.len();
+        self.buffer
+            .extend_from_slice(&bytes[..bytes.len().min(room)]);
+        if self.buffer.len() == BUFFER_SIZE {
+            self.status |= IMAGE_FULL;
+        }
+    }
+
+    fn decompress(&mut self, mut data: &[u8]) {
+        while let Some((&control, rest)) = data.split_first() {
+            if control & 0x80 != 0 {
+                let Some((&byte, rest)) = rest.split_first() else {
+                    break;
+                };
+                self.append(&vec![byte; (control & 0x7F) as usize + 2]);
+                data = rest;
+            } else {
+                let count = (control as usize + 1).min(rest.len());
+                self.append(&rest[..count]);
+                data = &rest[count..];
+            }
+        }
+    }
+
+    /// Renders the buffered tiles, 20 per row, onto the sheet
+    fn print(&mut self, palette: u8) {
+        // A zero palette is treated as the default one by the real printer
+        let palette = if palette == 0 { 0xE4 } else { palette };
+        let height = self.buffer.len() / (TILES_PER_ROW * 16) * 8;
+        for y in 0..height {
+            for x in 0..WIDTH {
+                let tile = (y / 8 * TILES_PER_ROW + x / 8) * 16 + y % 8 * 2;
+                let bit = 7 - x % 8;
+                let low = (self.buffer[tile] >> bit) & 1;
+                let high = (self.buffer[tile + 1] >> bit) & 1;
+                let shade = (palette >> ((high << 1 | low) * 2)) & 3;
+                self.sheet.extend_from_slice(&[SHADES[shade as usize]; 3]);
+            }
+        }
+    }
+}
+
+impl SerialDevice for Printer {
+    fn transfer(&mut self, byte: u8) -> u8 {
+        let mut reply = 0x00;
+        self.stage = match self.stage {
+            Stage::Magic1 if byte == 0x88 => Stage::Magic2,
+            Stage::Magic1 => Stage::Magic1,
+            Stage::Magic2 if byte == 0x33 => Stage::Command,
+            Stage::Magic2 => Stage::Magic1,
+            Stage::Command => {
+                self.command = byte;
+                self.checksum = byte as u16;
+                Stage::Compression
+            }
+            Stage::Compression => {
+                self.compressed = byte & 1 == 1;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                Stage::LengthLow
+            }
+            Stage::LengthLow => {
+                self.length = byte as u16;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                Stage::LengthHigh
+            }
+            Stage::LengthHigh => {
+                self.length |= (byte as u16) << 8;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                self.data.clear();
+                if self.length == 0 {
+                    Stage::ChecksumLow
+                } else {
+                    Stage::Data
+                }
+            }
+            Stage::Data => {
+                self.data.push(byte);
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                if self.data.len() == self.length as usize {
+                    Stage::ChecksumLow
+                } else {
+                    Stage::Data
+                }
+            }
+            Stage::ChecksumLow => {
+                self.received_checksum = byte as u16;
+                Stage::ChecksumHigh
+            }
+            Stage::ChecksumHigh => {
+                self.received_checksum |= (byte as u16) << 8;
+                self.run_command();
+                Stage::Alive
+            }
+            Stage::Alive => {
+                reply = 0x81;
+                Stage::Status
+            }
+            Stage::Status => {
+                reply = self.status;
+                if self.print_polls > 0 {
+                    self.print_polls -= 1;
+                    if self.print_polls == 0 {
+                        self.status &= !PRINTING;
+                    }
+                }
+                Stage::Magic1
+            }
+        };
+        reply
+    }
+}
+
+impl Drop for Printer {
+    fn drop(&mut self) {
+        if let Err(e) = self.cut_sheet() {
+            error!("{}", e);
+        }
+    }
+}
+
+#[test]
+fn printer_test() {
+    let dir = std::env::temp_dir().join(format!("redgb_printer_test_{}", std::process::id()));
+    let mut printer = Printer::new(&dir);
+    let send = |printer: &mut Printer, command: u8, compressed: bool, data: &[u8]| -> Vec<u8> {
+        let mut packet = vec![0x88, 0x33, command, compressed as u8];
+        packet.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        packet.extend_from_slice(data);
+        let checksum = packet[2..].iter().map(|&b| b as u16).sum::<u16>();
+        packet.extend_from_slice(&checksum.to_le_bytes());
+        packet.extend_from_slice(&[0, 0]);
+        packet.iter().map(|&byte| printer.transfer(byte)).collect()
+    };
+    let reply = send(&mut printer, INIT, false, &[]);
+    assert_eq!(reply[reply.len() - 2..], [0x81, 0x00]);
+
+    // Two rows of tiles, all black (color 3) but the first row of the first tile which is white
+    let mut tiles = vec![0x00, 0x00];
+    tiles.extend_from_slice(&[0xFF; 638]);
+    // 2 literals, then 638 0xFF in runs of at most 129
+    let mut compressed = vec![0x01, 0x00, 0x00];
+    for _ in 0..4 {
+        compressed.extend_from_slice(&[0xFF, 0xFF]);
+    }
+    compressed.extend_from_slice(&[0x80 | 120, 0xFF]);
+    let reply = send(&mut printer, DATA, true, &compressed);
+    assert_eq!(reply[reply.len() - 1], UNPROCESSED);
+    assert_eq!(printer.buffer, tiles);
+    let reply = send(&mut printer, DATA, false, &[]);
+    assert_eq!(reply[reply.len() - 1], UNPROCESSED);
+
+    let reply = send(&mut printer, PRINT, false, &[1, 0x03, 0xE4, 0x40]);
+    assert_eq!(reply[reply.len() - 1], PRINTING);
+    let mut polls = 1;
+    while send(&mut printer, STATUS, false, &[]).last() == Some(&PRINTING) {
+        polls += 1;
+    }
+    assert_eq!(polls, PRINT_POLLS);
+    assert_eq!(
+        send(&mut printer, 0x42, false, &[]).last(),
+        Some(&PACKET_ERROR)
+    );
+    let mut bad = vec![0x88, 0x33, STATUS, 0, 0, 0, 0, 0, 0, 0];
+    bad = bad.iter().map(|&byte| printer.transfer(byte)).collect();
+    assert_eq!(bad.last(), Some(&CHECKSUM_ERROR));
+
+    let path = printer.last_printout.clone().unwrap();
+    let decoder = png::Decoder::new(std::io::BufReader::new(File::open(&path).unwrap()));
+    let mut reader = decoder.read_info().unwrap();
+    let mut pixels = vec![0; reader.output_buffer_size().unwrap()];
+    let info = reader.next_frame(&mut pixels).unwrap();
+    assert_eq!((info.width, info.height), (160, 16));
+    assert_eq!(pixels[..3], [0xFF; 3]);
+    assert_eq!(pixels[8 * 3..9 * 3], [0x00; 3]);
+    assert_eq!(pixels[160 * 3..161 * 3], [0x00; 3]);
+
+    // 9 packets of 2 tile rows fill the printer
+    send(&mut printer, INIT, false, &[]);
+    for packet in 0..9 {
+        let status = *send(&mut printer, DATA, false, &[0x55; 640])
+            .last()
+            .unwrap();
+        assert_eq!(status & IMAGE_FULL != 0, packet == 8);
+    }
+    assert_eq!(printer.buffer.len(), BUFFER_SIZE);
+    std::fs::remove_dir_all(&dir).ok();
+}
@@ -0,0 +1,281 @@
+use std::path::{Path, PathBuf};
+
+use crate::{bus::Bus, error::GBError, mbc, rom::rom_info::ROMInfo};
+
+// File layout (all integers little endian)
+// "RGBS" | version: u16 | rom title: str | rom checksum: u16 | chunk*
+// chunk = tag: [u8; 4] | length: u32 | payload
+// Unknown chunks are skipped and missing ones leave that component untouched, so fields only ever
+// get appended to a chunk (bumping VERSION) or moved to a new chunk
+const MAGIC: [u8; 4] = *b"RGBS";
+pub const VERSION: u16 = 6;
+
+const BUS_TAG: [u8; 4] = *b"BUS ";
+const CPU_TAG: [u8; 4] = *b"CPU ";
+const MEM_TAG: [u8; 4] = *b"MEM ";
+const MBC_TAG: [u8; 4] = *b"MBC ";
+const PPU_TAG: [u8; 4] = *b"PPU ";
+const APU_TAG: [u8; 4] = *b"APU ";
+const TIMER_TAG: [u8; 4] = *b"TIMR";
+const JOYPAD_TAG: [u8; 4] = *b"JOYP";
+
+/// Tag and payload of a chunk
+type Chunk<'a> = ([u8; 4], &'a [u8]);
+
+/// Anything that is part of the machine state and has to survive a save state
+pub trait SaveState {
+    fn save_state(&self, w: &mut StateWriter);
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), GBError>;
+}
+
+#[derive(Default)]
+pub struct StateWriter {
+    buf: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn u8(&mut self, value: u8) {
+        self.buf.push(value);
+    }
+    pub fn bool(&mut self, value: bool) {
+        self.buf.push(value as u8);
+    }
+    pub fn u16(&mut self, value: u16) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+    pub fn u32(&mut self, value: u32) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+    pub fn u64(&mut self, value: u64) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+    pub fn f32(&mut self, value: f32) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+    pub fn opt_u8(&mut self, value: Option<u8>) {
+        self.bool(value.is_some());
+        self.u8(value.unwrap_or(0));
+    }
+    /// Length prefixed byte slice
+    pub fn bytes(&mut self, value: &[u8]) {
+        self.u32(value.len() as u32);
+        self.buf.extend_from_slice(value);
+    }
+    pub fn banks(&mut self, banks: &[Vec<u8>]) {
+        self.u32(banks.len() as u32);
+        for bank in banks {
+            self.bytes(bank);
+        }
+    }
+    pub fn chunk<T: SaveState + ?Sized>(&mut self, tag: [u8; 4], component: &T) {
+        let mut inner = StateWriter::default();
+        component.save_state(&mut inner);
+        self.buf.extend_from_slice(&tag);
+        self.bytes(&inner.buf);
+    }
+}
+
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    pub version: u16,
+}
+
+impl<'a> StateReader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], GBError> {
+        let end = self.pos.checked_add(len).ok_or(GBError::InvalidState)?;
+        let slice = self.data.get(self.pos..end).ok_or(GBError::InvalidState)?;
+        self.pos = end;
+        Ok(slice)
+    }
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], GBError> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+    pub fn u8(&mut self) -> Result<u8, GBError> {
+        Ok(self.take(1)?[0])
+    }
+    pub fn bool(&mut self) -> Result<bool, GBError> {
+        Ok(self.u8()? != 0)
+    }
+    pub fn u16(&mut self) -> Result<u16, GBError> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+    pub fn u32(&mut self) -> Result<u32, GBError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+    pub fn u64(&mut self) -> Result<u64, GBError> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+    pub fn f32(&mut self) -> Result<f32, GBError> {
+        Ok(f32::from_le_bytes(self.array()?))
+    }
+    pub fn opt_u8(&mut self) -> Result<Option<u8>, GBError> {
+        let is_some = self.bool()?;
+        let value = self.u8()?;
+        Ok(is_some.then_some(value))
+    }
+    pub fn bytes(&mut self) -> Result<&'a [u8], GBError> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+    /// Reads a length prefixed slice into `target`, the lengths have to match
+    pub fn fill(&mut self, target: &mut [u8]) -> Result<(), GBError> {
+        let bytes = self.bytes()?;
+        if bytes.len() != target.len() {
+            return Err(GBError::InvalidState);
+        }
+        target.copy_from_slice(bytes);
+        Ok(())
+    }
+    /// Reads banks written by [`StateWriter::banks`], the bank count and sizes have to match
+    pub fn banks(&mut self, banks: &mut [Vec<u8>]) -> Result<(), GBError> {
+        if self.u32()? as usize != banks.len() {
+            return Err(GBError::InvalidState);
+        }
+        for bank in banks.iter_mut() {
+            self.fill(bank)?;
+        }
+        Ok(())
+    }
+    fn str(&mut self) -> Result<String, GBError> {
+        Ok(String::from_utf8_lossy(self.bytes()?).to_string())
+    }
+}
+
+/// Serializes the whole machine, the ROM itself is not included
+pub fn save(bus: &Bus) -> Vec<u8> {
+    let header = bus.memory.controller.rom_header();
+    let mut w = StateWriter::default();
+    w.buf.extend_from_slice(&MAGIC);
+    w.u16(VERSION);
+    w.bytes(header.title.as_bytes());
+    w.u16(header.rom_checksum);
+    w.chunk(BUS_TAG, bus);
+    w.chunk(CPU_TAG, &bus.registers);
+    w.chunk(MEM_TAG, &bus.memory);
+    w.chunk(MBC_TAG, bus.memory.controller.as_ref());
+    w.chunk(PPU_TAG, &bus.ppu);
+    w.chunk(APU_TAG, &bus.apu);
+    w.chunk(TIMER_TAG, &bus.gbtimer);
+    w.chunk(JOYPAD_TAG, &bus.joypad);
+    w.buf
+}
+
+/// Restores a state produced by [`save`], fails if it was made with a different ROM. A state that
+/// turns out to be corrupt halfway through leaves the machine as it was
+pub fn load(bus: &mut Bus, data: &[u8]) -> Result<(), GBError> {
+    let (version, chunks) = parse(bus, data)?;
+    let backup = save(bus);
+    if let Err(e) = apply(bus, version, &chunks) {
+        let (version, chunks) = parse(bus, &backup)?;
+        apply(bus, version, &chunks)?;
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Checks the header and splits `data` into its chunks without touching the machine
+fn parse<'a>(bus: &Bus, data: &'a [u8]) -> Result<(u16, Vec<Chunk<'a>>), GBError> {
+    let mut r = StateReader {
+        data,
+        pos: 0,
+        version: 0,
+    };
+    if r.array::<4>()? != MAGIC {
+        return Err(GBError::InvalidState);
+    }
+    r.version = r.u16()?;
+    if r.version > VERSION {
+        return Err(GBError::UnsupportedStateVersion(r.version));
+    }
+    let header = bus.memory.controller.rom_header();
+    if r.str()? != header.title || r.u16()? != header.rom_checksum {
+        return Err(GBError::StateRomMismatch);
+    }
+    let mut chunks = vec![];
+    while r.pos < data.len() {
+        chunks.push((r.array::<4>()?, r.bytes()?));
+    }
+    Ok((r.version, chunks))
+}
+
+fn apply(bus: &mut Bus, version: u16, chunks: &[Chunk]) -> Result<(), GBError> {
+    for &(tag, data) in chunks {
+        let mut chunk = StateReader {
+            data,
+            pos: 0,
+            version,
+        };
+        match tag {
+            BUS_TAG => bus.load_state(&mut chunk)?,
+            CPU_TAG => bus.registers.load_state(&mut chunk)?,
+            MEM_TAG => bus.memory.load_state(&mut chunk)?,
+            MBC_TAG => bus.memory.controller.load_state(&mut chunk)?,
+            PPU_TAG => bus.ppu.load_state(&mut chunk)?,
+            APU_TAG => bus.apu.load_state(&mut chunk)?,
+            TIMER_TAG => bus.gbtimer.load_state(&mut chunk)?,
+            JOYPAD_TAG => bus.joypad.load_state(&mut chunk)?,
+            _ => log::warn!("Skipping unknown save state chunk {:?}", tag),
+        }
+    }
+    Ok(())
+}
+
+pub fn save_file(bus: &Bus, path: &Path) -> Result<(), GBError> {
+    log::info!("Saving state to {}", path.display());
+    std::fs::write(path, save(bus)).map_err(|_| GBError::SaveError)
+}
+
+pub fn load_file(bus: &mut Bus, path: &Path) -> Result<(), GBError> {
+    log::info!("Loading state from {}", path.display());
+    let data = std::fs::read(path).map_err(|_| GBError::LoadError)?;
+    load(bus, &data)
+}
+
+/// Same directory as the battery save, `<title>.state<slot>`
+pub fn state_path(rom_header: &ROMInfo, slot: u8) -> PathBuf {
+    mbc::save_path(rom_header).with_extension(format!("state{slot}"))
+}
+
+#[test]
+fn round_trip_test() {
+    use crate::gameboy::GameBoy;
+    // inc a, jr -3
+    let code = [0x3C, 0x18, 0xFD];
+    let mut gameboy = GameBoy::test_with_code(&code);
+    gameboy.run_frame().unwrap();
+    let snapshot = gameboy.save_state();
+    gameboy.run_frame().unwrap();
+    assert_ne!(gameboy.save_state(), snapshot);
+    gameboy.load_state(&snapshot).unwrap();
+    assert_eq!(gameboy.save_state(), snapshot);
+
+    let mut fresh = GameBoy::test_with_code(&code);
+    fresh.load_state(&snapshot).unwrap();
+    assert_eq!(fresh.save_state(), snapshot);
+    assert!(fresh.load_state(&snapshot[..snapshot.len() - 1]).is_err());
+
+    // A good CPU chunk followed by a broken memory chunk restores nothing
+    gameboy.run_frame().unwrap();
+    let before = gameboy.save_state();
+    let header = gameboy.bus.memory.controller.rom_header();
+    let mut w = StateWriter::default();
+    w.buf.extend_from_slice(&MAGIC);
+    w.u16(VERSION);
+    w.bytes(header.title.as_bytes());
+    w.u16(header.rom_checksum);
+    w.chunk(CPU_TAG, &fresh.bus.registers);
+    w.buf.extend_from_slice(&MEM_TAG);
+    w.bytes(&[1, 2, 3]);
+    assert!(gameboy.load_state(&w.buf).is_err());
+    assert_eq!(gameboy.save_state(), before);
+
+    let mut w = StateWriter::default();
+    w.banks(&[vec![0; 2]]);
+    let mut r = StateReader {
+        data: &w.buf,
+        pos: 0,
+        version: VERSION,
+    };
+    assert!(r.banks(&mut [vec![0; 4]]).is_err());
+}
@@ -0,0 +1,171 @@
+//! RGBDS symbol files
+//!
+//! RGBLINK's `-n` output has one `bank:addr label` line per symbol, e.g. `01:4A50 Main.loop`,
+//! with `;` comments. Labels show up in the debugger, the disassembler and CPU traces, addresses
+//! without a label of their own are shown relative to the closest one before them.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use crate::error::GBError;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub bank: usize,
+    pub addr: u16,
+    pub name: String,
+}
+
+#[derive(Debug, Default)]
+pub struct Symbols {
+    /// Sorted by address, then bank
+    list: Vec<Symbol>,
+    by_name: HashMap<String, usize>,
+}
+
+impl Symbols {
+    /// Parses a `.sym` file, lines that aren't `bank:addr label` are skipped
+    pub fn parse(text: &str) -> Self {
+        let mut list: Vec<Symbol> = text
+            .lines()
+            .filter_map(|line| {
+                let line = line.split(';').next()?.trim();
+                let (location, name) = line.split_once(char::is_whitespace)?;
+                let (bank, addr) = location.split_once(':')?;
+                Some(Symbol {
+                    bank: usize::from_str_radix(bank, 16).ok()?,
+                    addr: u16::from_str_radix(addr, 16).ok()?,
+                    name: name.trim().to_string(),
+                })
+            })
+            .collect();
+        list.sort_by_key(|symbol| (symbol.addr, symbol.bank));
+        let by_name = list
+            .iter()
+            .enumerate()
+            .map(|(index, symbol)| (symbol.name.clone(), index))
+            .collect();
+        Self { list, by_name }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, GBError> {
+        let text = std::fs::read_to_string(path).map_err(|_| GBError::LoadError)?;
+        Ok(Self::parse(&text))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.list.len()
+    }
+
+    pub fn find(&self, name: &str) -> Option<&Symbol> {
+        self.by_name.get(name).map(|&index| &self.list[index])
+    }
+
+    /// `Label` or `Label+offset` for `addr`, looking in `rom_bank` for 0x4000-0x7FFF. Only labels
+    /// in the same memory region count, so ROM addresses never show up relative to RAM labels
+    pub fn label(&self, addr: u16, rom_bank: usize) -> Option<String> {
+        let bank = bank_of(addr, rom_bank);
+        let start = region_start(addr);
+        let end = self.list.partition_point(|symbol| symbol.addr <= addr);
+        let symbol = self.list[..end]
+            .iter()
+            .rev()
+            .take_while(|symbol| symbol.addr >= start)
+            .find(|symbol| bank.is_none_or(|bank| bank == symbol.bank))?;
+        Some(match addr - symbol.addr {
+            0 => symbol.name.clone(),
+            offset => format!("{}+{offset}", symbol.name),
+        })
+    }
+
+    /// The label of an address exactly, for listings
+    pub fn exact(&self, addr: u16, rom_bank: usize) -> Option<&str> {
+        let bank = bank_of(addr, rom_bank);
+        let start = self.list.partition_point(|symbol| symbol.addr < addr);
+        self.list[start..]
+            .iter()
+            .take_while(|symbol| symbol.addr == addr)
+            .find(|symbol| bank.is_none_or(|bank| bank == symbol.bank))
+            .map(|symbol| symbol.name.as_str())
+    }
+
+    /// Replaces every `$XXXX` address operand in a disassembled `mnemonic` with its label
+    pub fn annotate(&self, mnemonic: &str, rom_bank: usize) -> String {
+        let Some((op, operands)) = mnemonic.split_once(' ') else {
+            return mnemonic.to_string();
+        };
+        let operands: Vec<String> = operands
+            .split(", ")
+            .map(|operand| {
+                let (open, inner, close) = match operand.strip_prefix('[') {
+                    Some(inner) => ("[", inner.trim_end_matches(']'), "]"),
+                    None => ("", operand, ""),
+                };
+                inner
+                    .strip_prefix('$')
+                    .filter(|hex| hex.len() == 4)
+                    .and_then(|hex| u16::from_str_radix(hex, 16).ok())
+                    .and_then(|addr| self.label(addr, rom_bank))
+                    .map_or(operand.to_string(), |label| format!("{open}{label}{close}"))
+            })
+            .collect();
+        format!("{op} {}", operands.join(", "))
+    }
+}
+
+/// `<rom>.sym`, where RGBLINK puts it by default
+pub fn sym_path(rom_path: &Path) -> PathBuf {
+    rom_path.with_extension("sym")
+}
+
+fn bank_of(addr: u16, rom_bank: usize) -> Option<usize> {
+    match addr {
+        0x0000..0x4000 => Some(0),
+        0x4000..0x8000 => Some(rom_bank),
+        // RAM banks aren't tracked, any bank matches
+        _ => None,
+    }
+}
+
+fn region_start(addr: u16) -> u16 {
+    match addr {
+        0x0000..0x4000 => 0x0000,
+        0x4000..0x8000 => 0x4000,
+        0x8000..0xA000 => 0x8000,
+        0xA000..0xC000 => 0xA000,
+        0xC000..0xD000 => 0xC000,
+        0xD000..0xE000 => 0xD000,
+        0xE000..0xFE00 => 0xE000,
+        0xFE00..0xFF00 => 0xFE00,
+        0xFF00..0xFF80 => 0xFF00,
+        _ => 0xFF80,
+    }
+}
+
+#[test]
+fn symbols_test() {
+    let symbols = Symbols::parse(
+        "; File generated by rgblink\n00:0150 Main\n00:0158 Main.loop\n\
+         01:4000 Bank1\n02:4000 Bank2 ; comment\n00:C000 wCounter\nnot a symbol\n",
+    );
+    assert_eq!(symbols.len(), 5);
+    assert_eq!(symbols.find("Main.loop").unwrap().addr, 0x158);
+    assert_eq!(symbols.label(0x158, 1).as_deref(), Some("Main.loop"));
+    assert_eq!(symbols.label(0x15B, 1).as_deref(), Some("Main.loop+3"));
+    assert_eq!(symbols.label(0x4010, 2).as_deref(), Some("Bank2+16"));
+    assert_eq!(symbols.label(0x4010, 3), None);
+    assert_eq!(symbols.label(0x100, 1), None);
+    assert_eq!(symbols.label(0xC001, 1).as_deref(), Some("wCounter+1"));
+    assert_eq!(symbols.exact(0x4000, 1), Some("Bank1"));
+    assert_eq!(symbols.exact(0x15B, 1), None);
+    assert_eq!(symbols.annotate("jp nz, $0158", 1), "jp nz, Main.loop");
+    assert_eq!(symbols.annotate("ld [$C000], a", 1), "ld [wCounter], a");
+    assert_eq!(symbols.annotate("ldh [$FF44], a", 1), "ldh [$FF44], a");
+    assert_eq!(symbols.annotate("nop", 1), "nop");
+}
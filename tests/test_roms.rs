@@ -0,0 +1,175 @@
+//! Conformance harness for the Blargg and Mooneye test ROMs
+//!
+//! The ROMs aren't redistributed with the repo, drop them anywhere under `test_roms/` (or point
+//! `REDGB_TEST_ROMS` at a directory) and they get picked up by file name. Missing ROMs are
+//! reported and skipped. Run with `cargo test --release --test test_roms -- --nocapture` to
+//! see the result table, debug builds are slow enough that cpu_instrs takes minutes.
+
+use std::{
+    env, fs,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+};
+
+use redgb::{GameBoy, cpu::sm83::SM83, rom::rom_parser};
+
+// Blargg ROMs report over the serial port, (file name, frame budget)
+const BLARGG_ROMS: [(&str, u32); 4] = [
+    ("cpu_instrs.gb", 4000),
+    ("instr_timing.gb", 300),
+    ("mem_timing.gb", 400),
+    ("halt_bug.gb", 300),
+];
+const MOONEYE_FRAMES: u32 = 300;
+// Mooneye ROMs execute `ld b, b` with these in B, C, D, E, H, L once they're done
+const MOONEYE_PASS: [u8; 6] = [3, 5, 8, 13, 21, 34];
+const MOONEYE_FAIL: [u8; 6] = [0x42; 6];
+
+// ROMs that fail on the current core, these are still run and shown in the table. Mooneye
+// entries are paths below the ROM directory, e.g. "acceptance/timer/rapid_toggle.gb"
+const BLARGG_KNOWN_FAILURES: [&str; 1] = ["halt_bug.gb"];
+const MOONEYE_KNOWN_FAILURES: [&str; 0] = [];
+
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    Pass,
+    Fail(String),
+    Timeout,
+    Panic,
+}
+
+fn rom_dir() -> PathBuf {
+    env::var_os("REDGB_TEST_ROMS")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| Path::new(env!("CARGO_MANIFEST_DIR")).join("test_roms"))
+}
+
+fn find_roms(dir: &Path, found: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            find_roms(&path, found);
+        } else if path.extension().is_some_and(|ext| ext == "gb") {
+            found.push(path);
+        }
+    }
+}
+
+fn boot(path: &Path) -> GameBoy {
+    let rom = fs::read(path).expect("Failed to read test ROM");
+    let info = rom_parser::parse_rom_header(&rom);
+    GameBoy::new(rom, info)
+}
+
+fn run_blargg(path: &Path, frames: u32) -> Outcome {
+    let mut gameboy = boot(path);
+    for _ in 0..frames {
+        if let Err(err) = SM83::step(&mut gameboy.bus) {
+            return Outcome::Fail(err.to_string());
+        }
+        let serial = String::from_utf8_lossy(&gameboy.bus.serial_message);
+        if serial.contains("Passed") {
+            return Outcome::Pass;
+        }
+        if serial.contains("Failed") {
+            return Outcome::Fail(serial.trim().lines().last().unwrap_or("").to_string());
+        }
+    }
+    Outcome::Timeout
+}
+
+fn run_mooneye(path: &Path) -> Outcome {
+    let mut gameboy = boot(path);
+    for _ in 0..MOONEYE_FRAMES {
+        if let Err(err) = SM83::step(&mut gameboy.bus) {
+            return Outcome::Fail(err.to_string());
+        }
+        let reg = &gameboy.bus.registers;
+        let signature = [reg.b, reg.c, reg.d, reg.e, reg.h, reg.l];
+        if signature == MOONEYE_PASS {
+            return Outcome::Pass;
+        }
+        if signature == MOONEYE_FAIL {
+            return Outcome::Fail("0x42 signature".to_string());
+        }
+    }
+    Outcome::Timeout
+}
+
+fn catch(run: impl FnOnce() -> Outcome) -> Outcome {
+    panic::catch_unwind(AssertUnwindSafe(run)).unwrap_or(Outcome::Panic)
+}
+
+/// Prints the per-ROM table and returns the ROMs that regressed, failures listed in `known` are
+/// expected
+fn report(suite: &str, results: &[(String, Outcome)], known: &[&str]) -> Vec<String> {
+    println!("\n{suite}");
+    println!("{:<48} | result", "rom");
+    println!("{:-<48}-+-------", "");
+    let mut regressions = vec![];
+    for (name, outcome) in results {
+        let status = match outcome {
+            Outcome::Pass => "PASS".to_string(),
+            Outcome::Fail(reason) => format!("FAIL ({reason})"),
+            Outcome::Timeout => "TIMEOUT".to_string(),
+            Outcome::Panic => "PANIC".to_string(),
+        };
+        let is_known = known.iter().any(|rom| Path::new(name).ends_with(rom));
+        println!(
+            "{name:<48} | {status}{}",
+            if is_known { " [known]" } else { "" }
+        );
+        if *outcome != Outcome::Pass && !is_known {
+            regressions.push(name.clone());
+        }
+    }
+    let passed = results.iter().filter(|(_, o)| *o == Outcome::Pass).count();
+    println!("{passed}/{} passed", results.len());
+    regressions
+}
+
+#[test]
+fn blargg() {
+    let mut roms = vec![];
+    find_roms(&rom_dir(), &mut roms);
+    let mut results = vec![];
+    for (name, frames) in BLARGG_ROMS {
+        match roms.iter().find(|path| path.ends_with(name)) {
+            Some(path) => results.push((name.to_string(), catch(|| run_blargg(path, frames)))),
+            None => println!("Skipping {name} (not found in {})", rom_dir().display()),
+        }
+    }
+    let regressions = report("Blargg", &results, &BLARGG_KNOWN_FAILURES);
+    assert!(regressions.is_empty(), "Failing ROMs: {regressions:?}");
+}
+
+#[test]
+fn mooneye_acceptance() {
+    let mut roms = vec![];
+    find_roms(&rom_dir(), &mut roms);
+    roms.retain(|path| path.components().any(|c| c.as_os_str() == "acceptance"));
+    roms.sort();
+    if roms.is_empty() {
+        println!(
+            "Skipping mooneye (no acceptance/ ROMs in {})",
+            rom_dir().display()
+        );
+    }
+    let root = rom_dir();
+    let results: Vec<(String, Outcome)> = roms
+        .iter()
+        .map(|path| {
+            let name = path
+                .strip_prefix(&root)
+                .unwrap_or(path)
+                .display()
+                .to_string();
+            (name, catch(|| run_mooneye(path)))
+        })
+        .collect();
+    let regressions = report("Mooneye acceptance", &results, &MOONEYE_KNOWN_FAILURES);
+    assert!(regressions.is_empty(), "Failing ROMs: {regressions:?}");
+}